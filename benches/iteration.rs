@@ -0,0 +1,46 @@
+// `cargo bench`で実行する。外部の crate を使わずに済むよう、
+// 標準のテストハーネスは使わずに`Instant`で時間を測るだけにしている。
+
+extern crate lists;
+
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const LEN: usize = 1_000_000;
+const ROUNDS: u32 = 20;
+
+fn bench<F: FnMut() -> u64>(name: &str, mut f: F) {
+    // 1回目はキャッシュを温めるためだけに使う。
+    black_box(f());
+    let mut best = Duration::from_secs(u64::MAX);
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        black_box(f());
+        best = best.min(start.elapsed());
+    }
+    let per_elem = best.as_nanos() as f64 / LEN as f64;
+    println!("{:<24} {:>10.3?} ({:.2} ns/elem)", name, best, per_elem);
+}
+
+fn main() {
+    let mut ok_stack = lists::ok_stack::List::new();
+    let mut unsafe_deque = lists::unsafe_deque::List::new();
+    let mut unrolled_8: lists::unrolled::List<u64, 8> = lists::unrolled::List::new();
+    let mut unrolled_64: lists::unrolled::List<u64, 64> = lists::unrolled::List::new();
+    let mut vec_deque = VecDeque::new();
+    for i in 0..LEN as u64 {
+        ok_stack.push(i);
+        unsafe_deque.push(i);
+        unrolled_8.push_back(i);
+        unrolled_64.push_back(i);
+        vec_deque.push_back(i);
+    }
+
+    println!("sum over {} u64 elements (best of {} rounds)", LEN, ROUNDS);
+    bench("ok_stack", || ok_stack.iter().sum());
+    bench("unsafe_deque", || unsafe_deque.iter().sum());
+    bench("unrolled (N = 8)", || unrolled_8.iter().sum());
+    bench("unrolled (N = 64)", || unrolled_64.iter().sum());
+    bench("VecDeque", || vec_deque.iter().sum());
+}
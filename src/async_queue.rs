@@ -0,0 +1,363 @@
+// `async`フィーチャーで使える、非同期タスク向けのアダプタ。
+// - `unsafe_deque::IntoIter`と`bad_safe_deque::IntoIter`を`futures_core::Stream`として使える。
+//   要素は既に手元にあるので、常にすぐ`Ready`を返す。
+// - `Queue`は`unsafe_deque::List`をバッファにした上限付きのキュー。
+//   満杯なら`push().await`が、空なら`pop().await`が、相手側が動くまで待つ。
+
+// edition 2015 では`async fn`が書けないので、`Push`と`Pop`は`Future`を手で実装している。
+// 呼び出し側 (edition 2018 以降) からは普通に`.await`できる。
+
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use futures_core::Stream;
+use node_alloc::NodeAllocator;
+use {bad_safe_deque, unsafe_deque};
+
+impl<T, A: NodeAllocator + Unpin> Stream for unsafe_deque::IntoIter<T, A> {
+    type Item = T;
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<T>> {
+        Poll::Ready(self.get_mut().next())
+    }
+}
+
+impl<T> Stream for bad_safe_deque::IntoIter<T> {
+    type Item = T;
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<T>> {
+        Poll::Ready(self.get_mut().next())
+    }
+}
+
+pub struct Queue<T> {
+    inner: Mutex<Inner<T>>,
+}
+
+struct Inner<T> {
+    list: unsafe_deque::List<T>,
+    // `unsafe_deque::List`は長さを持たないので、ここで数える。
+    len: usize,
+    cap: usize,
+    // 空きを待っている`Push`と、要素を待っている`Pop`。
+    // 状態が変わったら全員起こし、起きた側がもう一度確かめる。
+    push_wakers: Vec<Waker>,
+    pop_wakers: Vec<Waker>,
+}
+
+// 満杯なら空きができるまで待ってから`elem`を入れる。
+pub struct Push<'a, T: 'a> {
+    queue: &'a Queue<T>,
+    elem: Option<T>,
+}
+
+// 空なら要素が入るまで待ってから取り出す。
+pub struct Pop<'a, T: 'a> {
+    queue: &'a Queue<T>,
+}
+
+impl<T> Queue<T> {
+    // `cap`が 0 の場合は panic する。
+    pub fn new(cap: usize) -> Self {
+        assert!(cap > 0, "capacity must be at least 1");
+        Queue {
+            inner: Mutex::new(Inner {
+                list: unsafe_deque::List::new(),
+                len: 0,
+                cap,
+                push_wakers: Vec::new(),
+                pop_wakers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn push(&self, elem: T) -> Push<'_, T> {
+        Push { queue: self, elem: Some(elem) }
+    }
+
+    pub fn pop(&self) -> Pop<'_, T> {
+        Pop { queue: self }
+    }
+
+    // 満杯なら待たずに`Err`で`elem`を返す。
+    pub fn try_push(&self, elem: T) -> Result<(), T> {
+        let mut inner = self.lock();
+        if inner.len >= inner.cap {
+            return Err(elem);
+        }
+        inner.list.push(elem);
+        inner.len += 1;
+        let wakers = mem::take(&mut inner.pop_wakers);
+        drop(inner);
+        wake_all(wakers);
+        Ok(())
+    }
+
+    pub fn try_pop(&self) -> Option<T> {
+        let mut inner = self.lock();
+        let elem = inner.list.pop()?;
+        inner.len -= 1;
+        let wakers = mem::take(&mut inner.push_wakers);
+        drop(inner);
+        wake_all(wakers);
+        Some(elem)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lock().cap
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// `wake`は起こされたタスクをその場で poll するかもしれず、そのタスクはまたロックを取ろうとする。
+// なのでロックを持ったまま呼ばず、`mem::take`で取り出してロックを外してから呼ぶ。
+fn wake_all(wakers: Vec<Waker>) {
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+// 同じタスクが何度も`Pending`になっても、`Waker`が溜まり続けないようにする。
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+// `elem`をピン留めされた状態で扱う事はないので、`T`によらず動かしてよい。
+impl<'a, T> Unpin for Push<'a, T> {}
+
+impl<'a, T> Future for Push<'a, T> {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let mut inner = this.queue.lock();
+        if inner.len >= inner.cap {
+            register(&mut inner.push_wakers, cx.waker());
+            return Poll::Pending;
+        }
+        let elem = this.elem.take().expect("`Push` polled after completion");
+        inner.list.push(elem);
+        inner.len += 1;
+        let wakers = mem::take(&mut inner.pop_wakers);
+        drop(inner);
+        wake_all(wakers);
+        Poll::Ready(())
+    }
+}
+
+impl<'a, T> Future for Pop<'a, T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut inner = self.queue.lock();
+        match inner.list.pop() {
+            Some(elem) => {
+                inner.len -= 1;
+                let wakers = mem::take(&mut inner.push_wakers);
+                drop(inner);
+                wake_all(wakers);
+                Poll::Ready(elem)
+            }
+            None => {
+                register(&mut inner.pop_wakers, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::{self, Future};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use futures_core::Stream;
+    use {bad_safe_deque, unsafe_deque};
+    use super::Queue;
+
+    // 起こされた回数を数えるだけの`Waker`。
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // 1つのスレッドで複数の`Future`を順番に poll する最小限の executor。
+    // どれかが起こされている限り回し続け、誰も起こされないまま`Pending`が残れば
+    // デッドロックなので panic する。
+    fn run_all(mut futures: Vec<Pin<Box<dyn Future<Output = ()> + '_>>>) {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut done = vec![false; futures.len()];
+        loop {
+            let before = counter.0.load(Ordering::SeqCst);
+            for (fut, done) in futures.iter_mut().zip(done.iter_mut()) {
+                if !*done && fut.as_mut().poll(&mut cx).is_ready() {
+                    *done = true;
+                }
+            }
+            if done.iter().all(|&d| d) {
+                return;
+            }
+            assert!(counter.0.load(Ordering::SeqCst) != before, "deadlock: no future was woken");
+        }
+    }
+
+    fn next<S: Stream + Unpin>(stream: &mut S) -> Poll<Option<S::Item>> {
+        let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+        Pin::new(stream).poll_next(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn streams() {
+        let mut list = unsafe_deque::List::new();
+        list.push(1);
+        list.push(2);
+        let mut stream = list.into_iter();
+        assert_eq!(next(&mut stream), Poll::Ready(Some(1)));
+        assert_eq!(next(&mut stream), Poll::Ready(Some(2)));
+        assert_eq!(next(&mut stream), Poll::Ready(None));
+
+        let mut list = bad_safe_deque::List::new();
+        list.push_back(1);
+        list.push_front(0);
+        let mut stream = list.into_iter();
+        assert_eq!(next(&mut stream), Poll::Ready(Some(0)));
+        assert_eq!(next(&mut stream), Poll::Ready(Some(1)));
+        assert_eq!(next(&mut stream), Poll::Ready(None));
+    }
+
+    #[test]
+    fn try_ops() {
+        let queue = Queue::new(2);
+        assert_eq!(queue.try_pop(), None);
+        assert_eq!(queue.try_push(1), Ok(()));
+        assert_eq!(queue.try_push(2), Ok(()));
+        assert_eq!(queue.try_push(3), Err(3));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.try_pop(), Some(1));
+        assert_eq!(queue.try_pop(), Some(2));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn producer_consumer() {
+        let queue = Queue::new(2);
+        let mut received = Vec::new();
+        // 満杯で`Pending`になった回数。
+        let mut blocked = 0;
+
+        let mut next_elem = 0;
+        let mut push = None;
+        let producer = future::poll_fn(|cx| loop {
+            if push.is_none() {
+                if next_elem == 20 {
+                    return Poll::Ready(());
+                }
+                push = Some(queue.push(next_elem));
+                next_elem += 1;
+            }
+            match Pin::new(push.as_mut().unwrap()).poll(cx) {
+                Poll::Ready(()) => push = None,
+                Poll::Pending => {
+                    blocked += 1;
+                    return Poll::Pending;
+                }
+            }
+        });
+
+        // 1回の poll で1つだけ受け取り、生産側にも順番を回す。
+        let mut pop = None;
+        let consumer = future::poll_fn(|cx| {
+            let fut = pop.get_or_insert_with(|| queue.pop());
+            match Pin::new(fut).poll(cx) {
+                Poll::Ready(elem) => {
+                    pop = None;
+                    received.push(elem);
+                    if received.len() == 20 {
+                        return Poll::Ready(());
+                    }
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                Poll::Pending => Poll::Pending,
+            }
+        });
+
+        run_all(vec![Box::pin(producer), Box::pin(consumer)]);
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+        assert!(blocked > 0);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn pop_waits_for_push() {
+        let queue = Queue::new(1);
+        let waker_count = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(waker_count.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut pop = queue.pop();
+        assert_eq!(Pin::new(&mut pop).poll(&mut cx), Poll::Pending);
+        // 何度 poll しても`Waker`は1つしか登録されない。
+        assert_eq!(Pin::new(&mut pop).poll(&mut cx), Poll::Pending);
+        assert_eq!(queue.try_push(7), Ok(()));
+        assert_eq!(waker_count.0.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut pop).poll(&mut cx), Poll::Ready(7));
+
+        queue.try_push(1).unwrap();
+        let mut push = queue.push(2);
+        assert_eq!(Pin::new(&mut push).poll(&mut cx), Poll::Pending);
+        assert_eq!(queue.try_pop(), Some(1));
+        assert_eq!(waker_count.0.load(Ordering::SeqCst), 2);
+        assert_eq!(Pin::new(&mut push).poll(&mut cx), Poll::Ready(()));
+        assert_eq!(queue.try_pop(), Some(2));
+    }
+
+    // 起こされるとその場でキューを触る`Waker`。ロックを持ったまま起こすとデッドロックする。
+    struct ReentrantWaker(Arc<Queue<i32>>, AtomicUsize);
+
+    impl Wake for ReentrantWaker {
+        fn wake(self: Arc<Self>) {
+            self.1.store(self.0.len(), Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn wake_outside_lock() {
+        let queue = Arc::new(Queue::new(1));
+        let reentrant = Arc::new(ReentrantWaker(queue.clone(), AtomicUsize::new(usize::MAX)));
+        let waker = Waker::from(reentrant.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut pop = queue.pop();
+        assert_eq!(Pin::new(&mut pop).poll(&mut cx), Poll::Pending);
+        queue.try_push(1).unwrap();
+        assert_eq!(reentrant.1.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut pop).poll(&mut cx), Poll::Ready(1));
+
+        queue.try_push(2).unwrap();
+        let mut push = queue.push(3);
+        assert_eq!(Pin::new(&mut push).poll(&mut cx), Poll::Pending);
+        assert_eq!(queue.try_pop(), Some(2));
+        assert_eq!(reentrant.1.load(Ordering::SeqCst), 0);
+        assert_eq!(Pin::new(&mut push).poll(&mut cx), Poll::Ready(()));
+        assert_eq!(queue.try_pop(), Some(3));
+    }
+}
@@ -0,0 +1,212 @@
+// `persistent_stack::List`の先頭を複数のスレッドで共有する、追記専用のログ。
+// 書き込み側は`push`や`update`で新しい先頭を公開し、読み込み側は`snapshot`でその時点のリストを受け取る。
+// スナップショットは先頭の`Arc`を複製するだけなので O(1) で、以降はロックなしで辿れる。
+// ノードは変更されないので、あとから`push`されても手元のスナップショットは変わらない。
+
+// 先頭の付け替えは`Mutex`で守る。ロックを持つのは`Arc`を複製したり差し替えたりする間だけで、
+// `update`に渡した関数や古い先頭の drop はロックの外で行う。
+
+use std::fmt::Debug;
+use std::mem;
+use std::sync::{Mutex, MutexGuard};
+use persistent_stack::List;
+
+pub struct AtomicList<T> {
+    head: Mutex<List<T>>,
+}
+
+impl<T> AtomicList<T> {
+    pub fn new() -> Self {
+        AtomicList::from_list(List::new())
+    }
+
+    pub fn from_list(list: List<T>) -> Self {
+        AtomicList { head: Mutex::new(list) }
+    }
+
+    pub fn snapshot(&self) -> List<T> {
+        self.lock().clone()
+    }
+
+    pub fn push(&self, elem: T) {
+        self.lock().push_mut(elem);
+    }
+
+    // 現在の先頭が`current`と同じノードなら`new`に差し替え、古い先頭を返す。
+    // 違っていれば (他のスレッドが先に書き込んでいれば) 何もせず`new`を`Err`で返す。
+    // `current`が古い先頭の`Arc`を持っているので、そのノードが解放されて同じアドレスに
+    // 別のノードが作られる事はなく、ABA 問題は起こらない。
+    pub fn compare_exchange(&self, current: &List<T>, new: List<T>) -> Result<List<T>, List<T>> {
+        let mut head = self.lock();
+        if head.ptr_eq(current) {
+            Ok(mem::replace(&mut *head, new))
+        } else {
+            Err(new)
+        }
+    }
+
+    // スナップショットから`f`で新しいリストを作り、`compare_exchange`で公開する。
+    // 途中で他のスレッドが書き込んでいたら、新しいスナップショットで`f`をやり直す。
+    // そのため`f`は何度呼ばれてもよいものでなければならない。古い先頭を返す。
+    pub fn update<F: FnMut(&List<T>) -> List<T>>(&self, mut f: F) -> List<T> {
+        let mut current = self.snapshot();
+        loop {
+            let new = f(&current);
+            match self.compare_exchange(&current, new) {
+                Ok(prev) => return prev,
+                Err(_) => current = self.snapshot(),
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> List<T> {
+        self.head.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // ロックを持っている間に panic するのは`List`の操作の中だけで、その時も先頭は壊れていない。
+    fn lock(&self) -> MutexGuard<'_, List<T>> {
+        self.head.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// その時点のスナップショットを DOT 形式で出力する (デバッグ用)。
+impl<T: Debug> AtomicList<T> {
+    pub fn to_dot(&self) -> String {
+        self.snapshot().to_dot()
+    }
+}
+
+impl<T> Default for AtomicList<T> {
+    fn default() -> Self {
+        AtomicList::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use persistent_stack::List;
+    use super::AtomicList;
+
+    #[test]
+    fn basics() {
+        let log = AtomicList::new();
+        let empty = log.snapshot();
+        log.push(1);
+        log.push(2);
+        let snapshot = log.snapshot();
+        log.push(3);
+
+        // スナップショットはその時点のまま変わらない。
+        assert_eq!(empty.head(), None);
+        assert_eq!(snapshot.iter().collect::<Vec<_>>(), vec![&2, &1]);
+        assert_eq!(log.snapshot().iter().collect::<Vec<_>>(), vec![&3, &2, &1]);
+        assert!(log.snapshot().tail().ptr_eq(&snapshot));
+        assert_eq!(log.into_inner().iter().count(), 3);
+    }
+
+    #[test]
+    fn compare_exchange() {
+        let log = AtomicList::new();
+        let old = log.snapshot();
+        log.push(1);
+
+        // 先頭が変わっているので失敗し、渡したリストが戻ってくる。
+        let rejected = log.compare_exchange(&old, old.append(10)).err().unwrap();
+        assert_eq!(rejected.head(), Some(&10));
+
+        let current = log.snapshot();
+        let prev = log.compare_exchange(&current, current.append(2)).ok().unwrap();
+        assert!(prev.ptr_eq(&current));
+        assert_eq!(log.snapshot().iter().collect::<Vec<_>>(), vec![&2, &1]);
+    }
+
+    // `push_mut`がロックを持ったままリスト全体を確かめる事になるので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn concurrent_push_and_snapshot() {
+        const WRITERS: usize = 4;
+        const EVENTS: usize = 2000;
+        let log = AtomicList::new();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let writers: Vec<_> = (0..WRITERS).map(|w| {
+                let log = &log;
+                s.spawn(move || {
+                    for i in 0..EVENTS {
+                        log.push((w, i));
+                    }
+                })
+            }).collect();
+
+            // 書き込みと並行してスナップショットを取り、ロックなしで辿る。
+            // どのスナップショットでも、各書き込み側のイベントは新しい順に隙間なく並んでいる。
+            let done = &done;
+            let log = &log;
+            let reader = s.spawn(move || {
+                // 書き込みが先に終わっても、最低1回は確かめる。
+                let mut snapshots = 0;
+                loop {
+                    let finished = done.load(Ordering::SeqCst);
+                    let snapshot = log.snapshot();
+                    let mut expected: Vec<Option<usize>> = vec![None; WRITERS];
+                    for &(w, i) in snapshot.iter() {
+                        if let Some(next) = expected[w] {
+                            assert_eq!(i + 1, next);
+                        }
+                        expected[w] = Some(i);
+                    }
+                    assert!(expected.iter().all(|e| e.is_none_or(|i| i == 0)));
+                    snapshots += 1;
+                    if finished {
+                        return snapshots;
+                    }
+                }
+            });
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+            assert!(reader.join().unwrap() > 0);
+        });
+
+        assert_eq!(log.snapshot().iter().count(), WRITERS * EVENTS);
+    }
+
+    #[test]
+    fn concurrent_update() {
+        const THREADS: usize = 8;
+        const UPDATES: usize = 500;
+        let log = AtomicList::new();
+
+        // 先頭の値に 1 を足したものを積む。取りこぼしがあれば同じ値が2回積まれる。
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..UPDATES {
+                        log.update(|list: &List<usize>| list.append(list.head().map_or(0, |&n| n + 1)));
+                    }
+                });
+            }
+        });
+
+        let values: Vec<_> = log.snapshot().iter().cloned().collect();
+        let expected: Vec<_> = (0..THREADS * UPDATES).rev().collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn to_dot() {
+        let log = AtomicList::new();
+        log.push(1);
+        log.push(2);
+        let out = log.to_dot();
+        // 先頭は`log`と、出力のために取ったスナップショットの両方から指されている。
+        assert!(out.contains("\\n2\\nstrong=2\""));
+        assert!(out.contains("\\n1\\nstrong=1\""));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+    }
+}
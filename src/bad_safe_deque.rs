@@ -1,7 +1,13 @@
 // fourth.rs
 
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::cell::{RefCell, Ref, RefMut};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use std::vec;
+use dot::Dot;
 
 // 双方向リストは各ノードが互いの参照を持ち合う。
 // これらを安全に実装するために`Rc`を使う。
@@ -15,17 +21,39 @@ use std::cell::{RefCell, Ref, RefMut};
 // ただし、このルールをコンパイル時にではなくランタイム時にチェックし、
 // ルールが破られた場合は panic を起こす。
 
+// 両方向とも`Rc`で繋ぐと循環参照になり、`Rc`だけではメモリが解放されない。
+// そこで`prev`は`Weak`にして、強い参照は先頭から末尾への一方向だけにする。
+// こうすれば循環は構造的に起こらない。
+
 pub struct List<T> {
     head: Link<T>,
     tail: Link<T>,
+    // `NodeHandle`がどのリストのものかを見分けるための目印。
+    token: Rc<()>,
 }
 
 type Link<T> = Option<Rc<RefCell<Node<T>>>>;
 
+type WeakLink<T> = Option<Weak<RefCell<Node<T>>>>;
+
 struct Node<T> {
     elem: T,
     next: Link<T>,
-    prev: Link<T>,
+    prev: WeakLink<T>,
+}
+
+// リスト中の特定のノードを指すハンドル。中身は`Weak`なので、ハンドルを持っていても
+// ノードの解放は妨げないし、循環参照にもならない。
+// ノードがリストから取り除かれると、そのハンドルは以降どの操作でも`None`になる。
+pub struct NodeHandle<T> {
+    node: Weak<RefCell<Node<T>>>,
+    list: Weak<()>,
+}
+
+impl<T> Clone for NodeHandle<T> {
+    fn clone(&self) -> Self {
+        NodeHandle { node: self.node.clone(), list: self.list.clone() }
+    }
 }
 
 impl<T> Node<T> {
@@ -40,14 +68,30 @@ impl<T> Node<T> {
 
 impl<T> List<T> {
     pub fn new() -> Self {
-        List { head: None, tail: None }
+        List { head: None, tail: None, token: Rc::new(()) }
     }
 
+    // `Rc::new`は確保に失敗すると abort し、失敗を返す`try_new`は stable にない。
+    // 失敗しても`elem`を確実に返せる方法がないので、`try_push_*`は用意していない。
+
     pub fn push_front(&mut self, elem: T) {
+        self.push_front_node(Node::new(elem));
+        self.validate();
+    }
+
+    // 先頭にハンドル付きで追加する。ハンドルは要素がリストから取り除かれるまで使える。
+    pub fn push_front_with_handle(&mut self, elem: T) -> NodeHandle<T> {
         let new_head = Node::new(elem);
+        let handle = self.handle(&new_head);
+        self.push_front_node(new_head);
+        self.validate();
+        handle
+    }
+
+    fn push_front_node(&mut self, new_head: Rc<RefCell<Node<T>>>) {
         match self.head.take() {
             Some(old_head) => {
-                old_head.borrow_mut().prev = Some(new_head.clone());
+                old_head.borrow_mut().prev = Some(Rc::downgrade(&new_head));
                 new_head.borrow_mut().next = Some(old_head);
                 self.head = Some(new_head);
             }
@@ -59,11 +103,24 @@ impl<T> List<T> {
     }
 
     pub fn push_back(&mut self, elem: T) {
+        self.push_back_node(Node::new(elem));
+        self.validate();
+    }
+
+    // 末尾にハンドル付きで追加する。
+    pub fn push_back_with_handle(&mut self, elem: T) -> NodeHandle<T> {
         let new_tail = Node::new(elem);
+        let handle = self.handle(&new_tail);
+        self.push_back_node(new_tail);
+        self.validate();
+        handle
+    }
+
+    fn push_back_node(&mut self, new_tail: Rc<RefCell<Node<T>>>) {
         match self.tail.take() {
             Some(old_tail) => {
+                new_tail.borrow_mut().prev = Some(Rc::downgrade(&old_tail));
                 old_tail.borrow_mut().next = Some(new_tail.clone());
-                new_tail.borrow_mut().prev = Some(old_tail);
                 self.tail = Some(new_tail);
             }
             None => {
@@ -74,7 +131,7 @@ impl<T> List<T> {
     }
 
     pub fn pop_front(&mut self) -> Option<T> {
-        self.head.take().map(|old_head| {
+        let elem = self.head.take().map(|old_head| {
             match old_head.borrow_mut().next.take() {
                 Some(new_head) => {
                     new_head.borrow_mut().prev.take();
@@ -87,13 +144,17 @@ impl<T> List<T> {
             // 正しく実装できていれば`old_head`の参照は他にないはずなので
             // `ok`と`unwrap`を使ってエラーケースを無視する。
             Rc::try_unwrap(old_head).ok().unwrap().into_inner().elem
-        })
+        });
+        self.validate();
+        elem
     }
 
     pub fn pop_back(&mut self) -> Option<T> {
-        self.tail.take().map(|old_tail| {
-            match old_tail.borrow_mut().prev.take() {
+        let elem = self.tail.take().map(|old_tail| {
+            let prev = old_tail.borrow_mut().prev.take().and_then(|prev| prev.upgrade());
+            match prev {
                 Some(new_tail) => {
+                    // 手前のノードの`next`が`old_tail`への最後の強い参照。
                     new_tail.borrow_mut().next.take();
                     self.tail = Some(new_tail);
                 }
@@ -102,7 +163,9 @@ impl<T> List<T> {
                 }
             }
             Rc::try_unwrap(old_tail).ok().unwrap().into_inner().elem
-        })
+        });
+        self.validate();
+        elem
     }
 
     // 可能なら`RefCell`という実装の詳細を隠し、`Option<&T>`を返したいがそれはできない。
@@ -144,13 +207,364 @@ impl<T> List<T> {
     }
 }
 
+// `NodeHandle`を使って、位置のわかっているノードを O(1) で操作する。
+// 別のリストのハンドルや、既に取り除かれたノードのハンドルを渡した場合は
+// 何もせずに`None` (`insert_*`は要素をそのまま`Err`で返す) になる。
+impl<T> List<T> {
+    pub fn front_handle(&self) -> Option<NodeHandle<T>> {
+        self.head.as_ref().map(|node| self.handle(node))
+    }
+
+    pub fn back_handle(&self) -> Option<NodeHandle<T>> {
+        self.tail.as_ref().map(|node| self.handle(node))
+    }
+
+    pub fn get(&self, handle: &NodeHandle<T>) -> Option<Ref<'_, T>> {
+        self.node(handle).map(|node| Ref::map(node.borrow(), |node| &node.elem))
+    }
+
+    pub fn get_mut(&mut self, handle: &NodeHandle<T>) -> Option<RefMut<'_, T>> {
+        self.node(handle).map(|node| RefMut::map(node.borrow_mut(), |node| &mut node.elem))
+    }
+
+    pub fn remove(&mut self, handle: &NodeHandle<T>) -> Option<T> {
+        let node = self.upgrade(handle)?;
+        self.unlink(&node);
+        self.validate();
+        Some(into_elem(node))
+    }
+
+    pub fn insert_before(&mut self, handle: &NodeHandle<T>, elem: T) -> Result<NodeHandle<T>, T> {
+        let next = match self.upgrade(handle) {
+            Some(node) => node,
+            None => return Err(elem),
+        };
+        let prev = next.borrow().prev.as_ref().and_then(|prev| prev.upgrade());
+        let prev = match prev {
+            Some(prev) => prev,
+            None => {
+                // `push_front_with_handle`の中で不変条件を確かめるときに、余分な参照が残らないようにする。
+                drop(next);
+                return Ok(self.push_front_with_handle(elem));
+            }
+        };
+        let new_handle = self.link_between(prev, next, elem);
+        self.validate();
+        Ok(new_handle)
+    }
+
+    pub fn insert_after(&mut self, handle: &NodeHandle<T>, elem: T) -> Result<NodeHandle<T>, T> {
+        let prev = match self.upgrade(handle) {
+            Some(node) => node,
+            None => return Err(elem),
+        };
+        let next = prev.borrow().next.clone();
+        let next = match next {
+            Some(next) => next,
+            None => {
+                drop(prev);
+                return Ok(self.push_back_with_handle(elem));
+            }
+        };
+        let new_handle = self.link_between(prev, next, elem);
+        self.validate();
+        Ok(new_handle)
+    }
+
+    pub fn move_to_front(&mut self, handle: &NodeHandle<T>) -> bool {
+        match self.upgrade(handle) {
+            Some(node) => {
+                self.unlink(&node);
+                self.push_front_node(node);
+                self.validate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn move_to_back(&mut self, handle: &NodeHandle<T>) -> bool {
+        match self.upgrade(handle) {
+            Some(node) => {
+                self.unlink(&node);
+                self.push_back_node(node);
+                self.validate();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 隣り合う`prev`と`next`の間に新しいノードを繋ぐ。
+    fn link_between(&mut self, prev: Rc<RefCell<Node<T>>>, next: Rc<RefCell<Node<T>>>, elem: T) -> NodeHandle<T> {
+        let new_node = Node::new(elem);
+        let new_handle = self.handle(&new_node);
+        {
+            let mut node = new_node.borrow_mut();
+            node.prev = Some(Rc::downgrade(&prev));
+            node.next = Some(next.clone());
+        }
+        next.borrow_mut().prev = Some(Rc::downgrade(&new_node));
+        prev.borrow_mut().next = Some(new_node);
+        new_handle
+    }
+
+    fn handle(&self, node: &Rc<RefCell<Node<T>>>) -> NodeHandle<T> {
+        NodeHandle { node: Rc::downgrade(node), list: Rc::downgrade(&self.token) }
+    }
+
+    fn upgrade(&self, handle: &NodeHandle<T>) -> Option<Rc<RefCell<Node<T>>>> {
+        if handle.list.as_ptr() != Rc::as_ptr(&self.token) {
+            return None;
+        }
+        handle.node.upgrade()
+    }
+
+    // `upgrade`で得た`Rc`は一時的なものなので、そこから作った`Ref`は返せない。
+    // ただしハンドルの指すノードがこのリストに入っている限り、リスト自身が強い参照を
+    // 持っているし、ノードを取り除く操作はすべて`&mut self`を要求する。
+    // よって`&self`を借用している間はノードが解放されない事を、ここだけは自分で保証する。
+    fn node(&self, handle: &NodeHandle<T>) -> Option<&RefCell<Node<T>>> {
+        self.upgrade(handle).map(|node| unsafe { &*Rc::as_ptr(&node) })
+    }
+
+    // `node`をリストから外す。`node`はこのリストに入っていなければならない。
+    fn unlink(&mut self, node: &Rc<RefCell<Node<T>>>) {
+        let prev = node.borrow_mut().prev.take().and_then(|prev| prev.upgrade());
+        let next = node.borrow_mut().next.take();
+        match next {
+            Some(ref next) => next.borrow_mut().prev = prev.as_ref().map(Rc::downgrade),
+            None => self.tail = prev.clone(),
+        }
+        match prev {
+            Some(prev) => prev.borrow_mut().next = next,
+            None => self.head = next,
+        }
+    }
+}
+
+// 条件に合うノードをまとめて取り除く。
+// どれも先頭から順にノードを辿り、取り除くノードはその場で`unlink`するだけなので、
+// 残す要素を pop して push し直すような余計な確保は起きない。
+// クロージャには`&mut self`を借用したまま`&T`/`&mut T`を渡すので、
+// クロージャの中からリストに触れる事はできず、`RefCell`の借用が重なって panic する事もない。
+// クロージャや要素の drop が panic した場合も、それまでに取り除いた分が消えるだけで
+// リストは正しい状態のまま残る。
+impl<T> List<T> {
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        self.retain_mut(|elem| f(elem));
+    }
+
+    pub fn retain_mut<F: FnMut(&mut T) -> bool>(&mut self, mut f: F) {
+        for elem in self.extract_if(|elem| !f(elem)) {
+            drop(elem);
+        }
+    }
+
+    // `pred`が true を返した要素を取り除いて返すイテレータ。
+    // 最後まで回さずに drop した場合、残りの要素はリストに残る。
+    pub fn extract_if<F: FnMut(&mut T) -> bool>(&mut self, pred: F) -> ExtractIf<'_, T, F> {
+        let next = self.head.as_ref().map(Rc::downgrade);
+        ExtractIf { list: self, next, pred }
+    }
+
+    pub fn remove_first_matching<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Option<T> {
+        self.extract_if(|elem| f(elem)).next()
+    }
+
+    // `range`の位置にある要素を取り除いて返す。
+    // 範囲がリストの長さを超えている場合は panic する (`Vec::drain`と同じ)。
+    // 取り除いたノードをリストとして`Drain`に持たせると、`Drain`を`mem::forget`したときに
+    // 古いハンドルからそのノードを操作できてしまうので、要素は呼び出した時点で取り出しておく。
+    pub fn drain<R: RangeBounds<usize>>(&mut self, range: R) -> Drain<T> {
+        let len = self.count();
+        let start = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&n) => n + 1,
+            Bound::Excluded(&n) => n,
+            Bound::Unbounded => len,
+        };
+        assert!(start <= end, "drain start {} is greater than end {}", start, end);
+        assert!(end <= len, "drain end {} is out of bounds for length {}", end, len);
+
+        let mut cur = self.head.clone();
+        for _ in 0..start {
+            cur = cur.and_then(|node| node.borrow().next.clone());
+        }
+        let mut elems = Vec::with_capacity(end - start);
+        for _ in start..end {
+            let node = cur.take().unwrap();
+            cur = node.borrow().next.clone();
+            self.unlink(&node);
+            elems.push(into_elem(node));
+        }
+        // `cur`が持っている参照を消してから確かめる。
+        drop(cur);
+        self.validate();
+        Drain { iter: elems.into_iter() }
+    }
+
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    fn count(&self) -> usize {
+        let mut count = 0;
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            count += 1;
+            cur = node.borrow().next.clone();
+        }
+        count
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。呼べばビルドの種類によらず確かめる。
+// - `head`から`next`を辿ると`tail`で終わり、途中で同じノードに戻らない。
+// - 各ノードの`prev`は手前のノード (先頭なら`None`) を指している。
+// - 強い参照は手前のノードの`next` (先頭なら`head`) と、末尾なら`tail`の分だけ。
+//   `NodeHandle`や`prev`は`Weak`なので数に入らない。
+// `validate`フィーチャーを有効にすると、リストを変更する操作のたびに呼ばれる。
+impl<T> List<T> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut prev: Link<T> = None;
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            let addr = Rc::as_ptr(&node);
+            assert!(seen.insert(addr), "cycle: {:p} is reachable twice from `head`", addr);
+            let n = node.borrow();
+            let prev_addr = n.prev.as_ref().map_or(ptr::null(), Weak::as_ptr);
+            assert!(prev_addr == node_ptr(&prev), "`prev` of {:p} is {:p}, expected {:p}", addr, prev_addr, node_ptr(&prev));
+            // `node`として持っている分も足す。
+            let expected = 1 + n.next.is_none() as usize + 1;
+            assert_eq!(Rc::strong_count(&node), expected, "unexpected strong count of {:p}", addr);
+            cur = n.next.clone();
+            drop(n);
+            prev = Some(node);
+        }
+        assert!(node_ptr(&self.tail) == node_ptr(&prev), "`tail` is {:p}, but the last node is {:p}", node_ptr(&self.tail), node_ptr(&prev));
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// 各ノードには`Rc`の strong/weak count も書く。strong は手前のノードの`next` (先頭なら`head`) と、
+// 末尾なら`tail`の分。weak は次のノードの`prev`と、生きている`NodeHandle`の分になる。
+// `prev`は`Weak`のまま指す先を書くので、解放済みのノードを指していればそれも見える。
+impl<T: Debug> List<T> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", node_ptr(&self.head), "");
+        dot.root("tail", node_ptr(&self.tail), "");
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            // `cur`で1つ増えている分を引く。
+            let strong = Rc::strong_count(&node) - 1;
+            let weak = Rc::weak_count(&node);
+            let n = node.borrow();
+            let label = format!("{:?}\nstrong={} weak={}", n.elem, strong, weak);
+            dot.node(Rc::as_ptr(&node), &label);
+            dot.edge(Rc::as_ptr(&node), node_ptr(&n.next), "next");
+            if let Some(ref prev) = n.prev {
+                dot.edge(Rc::as_ptr(&node), Weak::as_ptr(prev), "prev");
+            }
+            cur = n.next.clone();
+        }
+        dot.finish()
+    }
+}
+
+fn node_ptr<T>(link: &Link<T>) -> *const RefCell<Node<T>> {
+    link.as_ref().map_or(ptr::null(), Rc::as_ptr)
+}
+
+// リストから外したノードから要素を取り出す。他に強い参照が残っていてはいけない。
+fn into_elem<T>(node: Rc<RefCell<Node<T>>>) -> T {
+    Rc::try_unwrap(node).ok().unwrap().into_inner().elem
+}
+
+pub struct ExtractIf<'a, T: 'a, F: FnMut(&mut T) -> bool> {
+    list: &'a mut List<T>,
+    // 次に`pred`に渡すノード。`Rc`で持つと、`ExtractIf`を`mem::forget`したときに
+    // strong count が1つ余ったままになり、以降の`pop_front`などの`try_unwrap`が失敗してしまう。
+    // `Weak`なら余るのは weak count だけで、取り出しには影響しない。
+    next: Option<Weak<RefCell<Node<T>>>>,
+    pred: F,
+}
+
+impl<'a, T, F: FnMut(&mut T) -> bool> Iterator for ExtractIf<'a, T, F> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        // `list`を借用しているので、まだ辿っていないノードが外される事はなく、`upgrade`は必ず成功する。
+        while let Some(node) = self.next.take().and_then(|next| next.upgrade()) {
+            self.next = node.borrow().next.as_ref().map(Rc::downgrade);
+            let matched = (self.pred)(&mut node.borrow_mut().elem);
+            if matched {
+                self.list.unlink(&node);
+                return Some(into_elem(node));
+            }
+        }
+        None
+    }
+}
+
+// 不変条件は取り出しが終わったところでまとめて確かめる。
+// `Drop`を実装するかどうかをフィーチャーで変えると、借用チェッカーの判断まで変わってしまうので、
+// 実装は常に置いておき、確かめるかどうかは`validate`の中で決める。
+impl<'a, T, F: FnMut(&mut T) -> bool> Drop for ExtractIf<'a, T, F> {
+    fn drop(&mut self) {
+        self.list.validate();
+    }
+}
+
+pub struct Drain<T> {
+    iter: vec::IntoIter<T>,
+}
+
+impl<T> Iterator for Drain<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for Drain<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.iter.next_back()
+    }
+}
+
 impl<T> Drop for List<T> {
-    // doubly-linked list なので、`Rc`に包まれた各ノードは互いの参照を持ち合う。
-    // このような相互 (循環) 参照がある場合、`Rc`は正しくメモリを解放しない
-    // (`List`を削除しても両端の要素の参照数が1つ減るだけ)。
-    // そのため手動でノードを辿って削除していく必要がある。
+    // `prev`が`Weak`なので循環参照にはならず、`head`を drop すれば全ノードが解放される。
+    // ただしデフォルトの drop は`next`を再帰的に辿るので、
+    // リストが長いと stack overflow が起きうる (`ok_stack`と同じ問題)。
+    // そのため先頭から1つずつ外していく。
+    // 要素の drop が panic しても、残りの要素は`DropGuard`が drop し続ける。
+    // (その途中でさらに panic すると abort になる。)
     fn drop(&mut self) {
-        while self.pop_front().is_some() {}
+        struct DropGuard<'a, T: 'a>(&'a mut List<T>);
+        impl<'a, T> Drop for DropGuard<'a, T> {
+            fn drop(&mut self) {
+                while self.0.pop_front().is_some() {}
+            }
+        }
+        let guard = DropGuard(self);
+        while guard.0.pop_front().is_some() {}
     }
 }
 
@@ -181,6 +595,11 @@ impl<T> DoubleEndedIterator for IntoIter<T> {
 
 #[cfg(test)]
 mod test {
+    use std::cell::Cell;
+    use std::mem;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+    use node_alloc::test::PanicOnDrop;
     use super::List;
 
     #[test]
@@ -259,4 +678,298 @@ mod test {
         assert_eq!(iter.next_back(), None);
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn handles() {
+        let mut list = List::new();
+        let one = list.push_back_with_handle(1);
+        let two = list.push_back_with_handle(2);
+        let three = list.push_back_with_handle(3);
+
+        assert_eq!(*list.get(&two).unwrap(), 2);
+        *list.get_mut(&two).unwrap() = 20;
+
+        let fifteen = list.insert_before(&two, 15).ok().unwrap();
+        list.insert_after(&two, 25).ok().unwrap();
+        list.insert_after(&three, 4).ok().unwrap();
+        list.insert_before(&one, 0).ok().unwrap();
+        assert_eq!(list.remove(&fifteen), Some(15));
+        assert!(list.get(&fifteen).is_none());
+        assert_eq!(list.remove(&fifteen), None);
+
+        assert!(list.move_to_front(&three));
+        assert!(list.move_to_back(&one));
+        assert_eq!(*list.get(&list.front_handle().unwrap()).unwrap(), 3);
+        assert_eq!(*list.get(&list.back_handle().unwrap()).unwrap(), 1);
+
+        assert_eq!(list.remove(&three), Some(3));
+        assert_eq!(list.remove(&one), Some(1));
+        assert_eq!(list.insert_after(&one, 5).err(), Some(5));
+        assert!(!list.move_to_front(&one));
+
+        assert_eq!(list.into_iter().collect::<Vec<_>>(), vec![0, 20, 25, 4]);
+    }
+
+    #[test]
+    fn handle_from_other_list() {
+        let mut a = List::new();
+        let mut b = List::new();
+        let handle = a.push_back_with_handle(1);
+        b.push_back(2);
+
+        assert!(b.get(&handle).is_none());
+        assert_eq!(b.remove(&handle), None);
+        assert_eq!(b.insert_after(&handle, 3).err(), Some(3));
+        assert_eq!(a.remove(&handle), Some(1));
+        assert_eq!(*b.peek_front().unwrap(), 2);
+    }
+
+    struct DropCounter(Rc<Cell<usize>>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn no_leaks() {
+        let drops = Rc::new(Cell::new(0));
+        let mut handles = Vec::new();
+        {
+            let mut list = List::new();
+            for i in 0..10 {
+                let handle = if i % 2 == 0 {
+                    list.push_back_with_handle(DropCounter(drops.clone()))
+                } else {
+                    list.push_front_with_handle(DropCounter(drops.clone()))
+                };
+                handles.push(handle);
+            }
+            list.move_to_front(&handles[4]);
+            list.move_to_back(&handles[7]);
+            drop(list.remove(&handles[3]));
+            drop(list.pop_back());
+            assert_eq!(drops.get(), 2);
+        }
+        // ハンドルが残っていても全要素が1回ずつ drop される。
+        assert_eq!(drops.get(), 10);
+        assert!(handles.iter().all(|h| h.node.upgrade().is_none()));
+    }
+
+    // `validate`が有効だと操作ごとにリスト全体を辿るので、長いリストのテストは飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn long_list_drop() {
+        let mut list = List::new();
+        for i in 0..200_000 {
+            list.push_back(i);
+        }
+        drop(list);
+    }
+
+    #[test]
+    fn drop_panic_safety() {
+        let drops: Vec<_> = (0..5).map(|_| Cell::new(0)).collect();
+        let mut list = List::new();
+        for (i, d) in drops.iter().enumerate() {
+            list.push_back(PanicOnDrop(d, i == 2));
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(move || drop(list)));
+        assert!(result.is_err());
+        // panic した要素のあとも drop は続き、どの要素も1回だけ drop される。
+        assert!(drops.iter().all(|d| d.get() == 1));
+    }
+
+    // 先頭から`next`を、末尾から`prev`を辿った結果が一致する事も確かめる。
+    fn contents(list: &List<i32>) -> Vec<i32> {
+        let mut forward = Vec::new();
+        let mut cur = list.head.clone();
+        while let Some(node) = cur {
+            forward.push(node.borrow().elem);
+            cur = node.borrow().next.clone();
+        }
+        let mut backward = Vec::new();
+        let mut cur = list.tail.clone();
+        while let Some(node) = cur {
+            backward.push(node.borrow().elem);
+            cur = node.borrow().prev.as_ref().and_then(|prev| prev.upgrade());
+        }
+        backward.reverse();
+        assert_eq!(forward, backward);
+        forward
+    }
+
+    fn list_of(elems: &[i32]) -> List<i32> {
+        let mut list = List::new();
+        for &e in elems {
+            list.push_back(e);
+        }
+        list
+    }
+
+    #[test]
+    fn retain() {
+        let mut list = list_of(&[1, 2, 3, 4, 5, 6]);
+        list.retain(|&e| e % 2 == 0);
+        assert_eq!(contents(&list), vec![2, 4, 6]);
+
+        list.retain_mut(|e| {
+            *e *= 10;
+            *e != 60
+        });
+        assert_eq!(contents(&list), vec![20, 40]);
+
+        // 先頭と末尾を取り除いたあとも両端の操作が正しく動く。
+        let mut list = list_of(&[1, 2, 3]);
+        list.retain(|&e| e == 2);
+        list.push_front(0);
+        list.push_back(4);
+        assert_eq!(contents(&list), vec![0, 2, 4]);
+
+        list.retain(|_| false);
+        assert_eq!(contents(&list), vec![]);
+        assert!(list.peek_back().is_none());
+    }
+
+    #[test]
+    fn retain_keeps_handles() {
+        let mut list = List::new();
+        let handles: Vec<_> = (0..5).map(|i| list.push_back_with_handle(i)).collect();
+        list.retain(|&e| e != 1 && e != 4);
+        assert!(list.get(&handles[1]).is_none());
+        assert!(list.get(&handles[4]).is_none());
+        assert_eq!(*list.get(&handles[3]).unwrap(), 3);
+        assert!(list.move_to_front(&handles[3]));
+        assert_eq!(contents(&list), vec![3, 0, 2]);
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut list = list_of(&[1, 2, 3, 4, 5, 6]);
+        let evens: Vec<_> = list.extract_if(|e| *e % 2 == 0).collect();
+        assert_eq!(evens, vec![2, 4, 6]);
+        assert_eq!(contents(&list), vec![1, 3, 5]);
+
+        // 途中で止めれば残りはそのまま。
+        let mut list = list_of(&[1, 2, 3, 4]);
+        assert_eq!(list.extract_if(|_| true).next(), Some(1));
+        assert_eq!(contents(&list), vec![2, 3, 4]);
+
+        assert_eq!(list.remove_first_matching(|&e| e > 2), Some(3));
+        assert_eq!(list.remove_first_matching(|&e| e > 10), None);
+        assert_eq!(contents(&list), vec![2, 4]);
+    }
+
+    #[test]
+    fn extract_if_forget() {
+        let mut list = list_of(&[1, 2, 3, 4, 5]);
+        let mut iter = list.extract_if(|e| *e == 2);
+        assert_eq!(iter.next(), Some(2));
+        mem::forget(iter);
+
+        // 忘れられた`ExtractIf`が次のノードの strong count を残していないので、全部取り出せる。
+        assert_eq!(list.pop_front(), Some(1));
+        assert_eq!(list.pop_back(), Some(5));
+        assert_eq!(list.pop_front(), Some(3));
+        assert_eq!(list.pop_front(), Some(4));
+        assert_eq!(list.pop_front(), None);
+    }
+
+    #[test]
+    fn drain() {
+        let mut list = list_of(&[0, 1, 2, 3, 4, 5]);
+        assert_eq!(list.drain(1..3).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(contents(&list), vec![0, 3, 4, 5]);
+
+        // 回さずに drop しても範囲の要素は取り除かれる。
+        drop(list.drain(2..=3));
+        assert_eq!(contents(&list), vec![0, 3]);
+
+        let mut drain = list.drain(..);
+        assert_eq!(drain.next_back(), Some(3));
+        assert_eq!(drain.next(), Some(0));
+        drop(drain);
+        assert_eq!(contents(&list), vec![]);
+
+        let mut list = list_of(&[0, 1, 2]);
+        assert_eq!(list.drain(3..).count(), 0);
+        assert_eq!(list.drain(..0).count(), 0);
+        list.clear();
+        assert_eq!(contents(&list), vec![]);
+        list.push_back(7);
+        assert_eq!(contents(&list), vec![7]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn drain_out_of_bounds() {
+        list_of(&[0, 1, 2]).drain(1..4);
+    }
+
+    #[test]
+    fn retain_panic_safety() {
+        let mut list = list_of(&[0, 1, 2, 3, 4, 5]);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            list.retain(|&e| {
+                if e == 3 {
+                    panic!("predicate panicked");
+                }
+                e % 2 == 0
+            })
+        }));
+        assert!(result.is_err());
+        // panic するまでに取り除いた要素だけが消え、リストは壊れていない。
+        assert_eq!(contents(&list), vec![0, 2, 3, 4, 5]);
+        list.push_back(6);
+        assert_eq!(list.pop_front(), Some(0));
+        assert_eq!(contents(&list), vec![2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut list = List::new();
+        list.push_back(1);
+        let handle = list.push_back_with_handle(2);
+        list.push_back(3);
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert!(out.contains("\"tail\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 2);
+        assert_eq!(out.matches(" [label=\"prev\"]").count(), 2);
+        // 先頭は`head`からだけ、末尾は手前のノードと`tail`から指されている。
+        assert!(out.contains("\\n1\\nstrong=1 weak=1\""));
+        assert!(out.contains("\\n2\\nstrong=1 weak=2\""));
+        assert!(out.contains("\\n3\\nstrong=2 weak=0\""));
+        drop(handle);
+
+        let out = List::<i32>::new().to_dot();
+        assert!(!out.contains("->"));
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list = List::new();
+        list.push_back(1);
+        let two = list.push_back_with_handle(2);
+        list.push_back(3);
+        list.debug_assert_invariants();
+
+        // 外したノードへの`Weak`を`prev`に残してしまった状態。
+        let stale = super::Node::new(0);
+        let node = two.node.upgrade().unwrap();
+        let prev = node.borrow_mut().prev.replace(Rc::downgrade(&stale));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        node.borrow_mut().prev = prev;
+        drop(node);
+        list.debug_assert_invariants();
+
+        // `tail`を更新し忘れた状態。
+        let tail = list.tail.take();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.tail = tail;
+        list.debug_assert_invariants();
+    }
 }
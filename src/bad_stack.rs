@@ -18,6 +18,8 @@
 // }
 
 use std::mem;
+use std::ptr;
+use dot::Dot;
 
 pub struct List {
     head: Link,
@@ -65,6 +67,33 @@ impl List {
             }
         }
     }
+
+    // ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", node_ptr(&self.head), "");
+        let mut link = &self.head;
+        while let Link::More(ref node) = *link {
+            dot.node(&**node as *const Node, &node.elem.to_string());
+            dot.edge(&**node as *const Node, node_ptr(&node.next), "next");
+            link = &node.next;
+        }
+        dot.finish()
+    }
+}
+
+// 他のリストと揃えるために置いているだけで、確かめる事は何もない。
+// ノードは`Box`で1つずつ所有されているので、安全なコードからは循環も共有も作れず、
+// 壊れた状態を表す事自体ができない。
+impl List {
+    pub fn debug_assert_invariants(&self) {}
+}
+
+fn node_ptr(link: &Link) -> *const Node {
+    match *link {
+        Link::Empty => ptr::null(),
+        Link::More(ref node) => &**node,
+    }
 }
 
 // デフォルトの destructor は再帰的にノードをたどるため、
@@ -108,4 +137,17 @@ mod test {
         assert_eq!(list.pop(), Some(1));
         assert_eq!(list.pop(), None);
     }
+
+    #[test]
+    fn to_dot() {
+        let mut list = List::new();
+        assert!(!list.to_dot().contains("->"));
+        list.push(1);
+        list.push(2);
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert!(out.contains("\\n2\"]"));
+        assert!(out.contains("\\n1\"]"));
+    }
 }
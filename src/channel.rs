@@ -0,0 +1,352 @@
+// `unsafe_deque::List`をバッファにした、依存なしの MPSC チャネル。
+// バッファは`Mutex`で守り、`Condvar`で「要素が入った」「空きができた」を待つ。
+// `Sender`は何個でも clone できるが、`Receiver`は1つだけ。
+
+// すべての`Sender`が drop されると、バッファが空になった時点で`recv`はエラーを返す。
+// `Receiver`が drop されると、`send`は送ろうとした値をエラーとして返す。
+// `bounded`で作ったチャネルはバッファが満杯の間`send`がブロックする。
+
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use unsafe_deque::List;
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    // 要素が入ったか、`Sender`がすべていなくなったときに起こす。
+    not_empty: Condvar,
+    // 空きができたか、`Receiver`がいなくなったときに起こす。
+    not_full: Condvar,
+}
+
+struct State<T> {
+    queue: List<T>,
+    // `unsafe_deque::List`は長さを持たないので、ここで数える。
+    len: usize,
+    cap: Option<usize>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecvError;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+// バッファの大きさに上限のないチャネル。`send`はブロックしない。
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new(None)
+}
+
+// バッファに`cap`個まで入るチャネル。満杯の間は`send`がブロックする。
+// `cap`が 0 の場合は panic する。
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(cap > 0, "capacity must be at least 1");
+    new(Some(cap))
+}
+
+fn new<T>(cap: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: List::new(),
+            len: 0,
+            cap,
+            senders: 1,
+            receiver_alive: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+impl<T> Shared<T> {
+    // ロックを持ったまま panic するのは`T`の drop くらいで、その場合も`State`は壊れていない。
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> State<T> {
+    fn is_full(&self) -> bool {
+        self.cap.is_some_and(|cap| self.len >= cap)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let elem = self.queue.pop()?;
+        self.len -= 1;
+        Some(elem)
+    }
+}
+
+impl<T> Sender<T> {
+    // `Receiver`が既に drop されていれば、`value`をそのまま`Err`で返す。
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+        while state.receiver_alive && state.is_full() {
+            state = self.shared.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if !state.receiver_alive {
+            return Err(SendError(value));
+        }
+        state.queue.push(value);
+        state.len += 1;
+        drop(state);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    // 要素が届くまでブロックする。
+    // `Sender`がすべていなくなっていて、バッファも空なら`Err`。
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(elem) = state.pop() {
+                drop(state);
+                self.shared.not_full.notify_one();
+                return Ok(elem);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.shared.not_empty.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match state.pop() {
+            Some(elem) => {
+                drop(state);
+                self.shared.not_full.notify_one();
+                Ok(elem)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    // `timeout`が経っても要素が届かなければ`Timeout`。
+    // `Condvar`は理由なく起きる事があるので、期限は最初に決めておいて残り時間だけ待つ。
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(elem) = state.pop() {
+                drop(state);
+                self.shared.not_full.notify_one();
+                return Ok(elem);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.shared.not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_alive = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+// `T`が`Debug`でなくても`unwrap`できるように、中身は表示しない (`std::sync::mpsc`と同じ)。
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SendError { .. }")
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("sending on a closed channel")
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("receiving on a closed channel")
+    }
+}
+
+impl Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TryRecvError::Empty => f.write_str("receiving on an empty channel"),
+            TryRecvError::Disconnected => f.write_str("receiving on a closed channel"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting on channel"),
+            RecvTimeoutError::Disconnected => f.write_str("receiving on a closed channel"),
+        }
+    }
+}
+
+impl Error for RecvTimeoutError {}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+    use super::{bounded, channel, RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+    #[test]
+    fn basics() {
+        let (tx, rx) = channel();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+
+        // 送信側がいなくなっても、残っている要素は受け取れる。
+        tx.send(3).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(3));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn receiver_dropped() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send(String::from("a")), Err(SendError(String::from("a"))));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (tx, rx) = channel();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx.send(1).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(1));
+        handle.join().unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn multiple_producers() {
+        let (tx, rx) = channel();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        tx.send(t * 1000 + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut received = Vec::new();
+        while let Ok(v) = rx.recv() {
+            received.push(v);
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 送信側ごとの順番は保たれる。
+        for t in 0..4 {
+            let from_t: Vec<_> = received.iter().cloned().filter(|v| v / 1000 == t).collect();
+            assert_eq!(from_t, (t * 1000..(t + 1) * 1000).collect::<Vec<_>>());
+        }
+        assert_eq!(received.len(), 4000);
+    }
+
+    #[test]
+    fn bounded_blocks() {
+        let (tx, rx) = bounded(2);
+        let handle = thread::spawn(move || {
+            for i in 0..100 {
+                tx.send(i).unwrap();
+            }
+        });
+        // 受信側が遅くても、バッファに溜まるのは高々2個。
+        for i in 0..100 {
+            thread::sleep(Duration::from_micros(100));
+            assert!(rx.shared.lock().len <= 2);
+            assert_eq!(rx.recv(), Ok(i));
+        }
+        handle.join().unwrap();
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn bounded_receiver_dropped() {
+        let (tx, rx) = bounded(1);
+        tx.send(1).unwrap();
+        let handle = thread::spawn(move || tx.send(2));
+        // 満杯で待っている送信側も、受信側が drop されれば値を返して戻る。
+        thread::sleep(Duration::from_millis(10));
+        drop(rx);
+        assert_eq!(handle.join().unwrap(), Err(SendError(2)));
+    }
+}
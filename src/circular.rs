@@ -0,0 +1,800 @@
+// 番兵 (sentinel) ノードを使った循環双方向リスト。
+// `bad_safe_deque`や`unsafe_deque`では、リストが空かどうかで`Option`や null の
+// 場合分けが push/pop のたびに必要だった。
+// 要素を持たない番兵ノードを1つ確保しておき、先頭と末尾を番兵で繋いで輪にすると、
+// どのノードにも必ず前後が存在するので、繋ぎ変えに場合分けが要らなくなる。
+
+// 空のリストは、番兵の`prev`と`next`が番兵自身を指している状態。
+// 先頭は`ghost.next`、末尾は`ghost.prev`になる。
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use dot::Dot;
+
+pub struct List<T> {
+    ghost: *mut Node<T>,
+    len: usize,
+    _marker: PhantomData<Box<Node<T>>>,
+}
+
+struct Node<T> {
+    // 番兵の`elem`だけは初期化されない。
+    elem: MaybeUninit<T>,
+    prev: *mut Node<T>,
+    next: *mut Node<T>,
+}
+
+pub struct Iter<'a, T: 'a> {
+    front: *const Node<T>,
+    back: *const Node<T>,
+    len: usize,
+    _marker: PhantomData<&'a T>,
+}
+
+pub struct IterMut<'a, T: 'a> {
+    front: *mut Node<T>,
+    back: *mut Node<T>,
+    len: usize,
+    _marker: PhantomData<&'a mut T>,
+}
+
+pub struct IntoIter<T>(List<T>);
+
+// 末尾の次は先頭に戻る (番兵は飛ばす) カーソル。
+// リストが空のときだけ、カーソルは番兵を指していて`current`が`None`になる。
+pub struct CursorMut<'a, T: 'a> {
+    list: &'a mut List<T>,
+    cur: *mut Node<T>,
+}
+
+impl<T> List<T> {
+    pub fn new() -> Self {
+        let ghost = Box::into_raw(Box::new(Node {
+            elem: MaybeUninit::uninit(),
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        }));
+        unsafe {
+            (*ghost).prev = ghost;
+            (*ghost).next = ghost;
+        }
+        List { ghost, len: 0, _marker: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, elem: T) {
+        unsafe {
+            let ghost = self.ghost;
+            self.insert_between(ghost, (*ghost).next, elem);
+        }
+    }
+
+    pub fn push_back(&mut self, elem: T) {
+        unsafe {
+            let ghost = self.ghost;
+            self.insert_between((*ghost).prev, ghost, elem);
+        }
+    }
+
+    // 空かどうかは`Option`を返すためだけに見ている。繋ぎ変え自体は場合分けしない。
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        unsafe { Some(self.unlink((*self.ghost).next)) }
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        unsafe { Some(self.unlink((*self.ghost).prev)) }
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        unsafe { Some((*(*self.ghost).next).elem.assume_init_ref()) }
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        unsafe { Some((*(*self.ghost).prev).elem.assume_init_ref()) }
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            return None;
+        }
+        unsafe { Some((*(*self.ghost).next).elem.assume_init_mut()) }
+    }
+
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            return None;
+        }
+        unsafe { Some((*(*self.ghost).prev).elem.assume_init_mut()) }
+    }
+
+    // 先頭の`n`個を末尾へ回す (`VecDeque::rotate_left`と同じ)。
+    // 要素は動かさず、番兵を輪の中で移すだけで済む。
+    // 番兵の移動先を探すのに、両端の近い方から min(n, len - n) 回辿る。
+    pub fn rotate_left(&mut self, n: usize) {
+        assert!(n <= self.len, "cannot rotate by more than the length");
+        unsafe {
+            let after = if n <= self.len - n {
+                self.nth_from_front(n)
+            } else {
+                self.nth_from_back(self.len - n)
+            };
+            self.move_ghost_after(after);
+        }
+    }
+
+    // 末尾の`n`個を先頭へ回す。
+    pub fn rotate_right(&mut self, n: usize) {
+        assert!(n <= self.len, "cannot rotate by more than the length");
+        self.rotate_left(self.len - n);
+    }
+
+    // `other`の要素をすべて末尾に繋ぐ。要素数によらず O(1)。
+    pub fn append(&mut self, other: &mut List<T>) {
+        unsafe {
+            let last = (*self.ghost).prev;
+            self.splice_after(last, other);
+        }
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        let cur = unsafe { (*self.ghost).next };
+        CursorMut { list: self, cur }
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T> {
+        let cur = unsafe { (*self.ghost).prev };
+        CursorMut { list: self, cur }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        unsafe {
+            Iter {
+                front: (*self.ghost).next,
+                back: (*self.ghost).prev,
+                len: self.len,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        unsafe {
+            IterMut {
+                front: (*self.ghost).next,
+                back: (*self.ghost).prev,
+                len: self.len,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    unsafe fn insert_between(&mut self, prev: *mut Node<T>, next: *mut Node<T>, elem: T) -> *mut Node<T> {
+        let node = Box::into_raw(Box::new(Node { elem: MaybeUninit::new(elem), prev, next }));
+        (*prev).next = node;
+        (*next).prev = node;
+        self.len += 1;
+        self.validate();
+        node
+    }
+
+    // `node`は番兵以外のノードでなければならない。
+    unsafe fn unlink(&mut self, node: *mut Node<T>) -> T {
+        let node = Box::from_raw(node);
+        (*node.prev).next = node.next;
+        (*node.next).prev = node.prev;
+        self.len -= 1;
+        self.validate();
+        node.elem.assume_init_read()
+    }
+
+    // `at`の直後に`other`の全要素を繋ぎ、`other`を空にする。
+    unsafe fn splice_after(&mut self, at: *mut Node<T>, other: &mut List<T>) {
+        if other.is_empty() {
+            return;
+        }
+        let first = (*other.ghost).next;
+        let last = (*other.ghost).prev;
+        let next = (*at).next;
+        (*at).next = first;
+        (*first).prev = at;
+        (*last).next = next;
+        (*next).prev = last;
+        self.len += other.len;
+
+        (*other.ghost).next = other.ghost;
+        (*other.ghost).prev = other.ghost;
+        other.len = 0;
+        self.validate();
+        other.validate();
+    }
+
+    // 先頭から`n`番目 (0 なら番兵) のノード。
+    unsafe fn nth_from_front(&self, n: usize) -> *mut Node<T> {
+        let mut node = self.ghost;
+        for _ in 0..n {
+            node = (*node).next;
+        }
+        node
+    }
+
+    // 末尾から`n`個戻った位置のノード (0 なら番兵の1つ手前、つまり末尾)。
+    // `nth_from_front(len - n)`と同じノードを返す。
+    unsafe fn nth_from_back(&self, n: usize) -> *mut Node<T> {
+        let mut node = (*self.ghost).prev;
+        for _ in 0..n {
+            node = (*node).prev;
+        }
+        node
+    }
+
+    unsafe fn move_ghost_after(&mut self, at: *mut Node<T>) {
+        let ghost = self.ghost;
+        if at == ghost {
+            return;
+        }
+        (*(*ghost).prev).next = (*ghost).next;
+        (*(*ghost).next).prev = (*ghost).prev;
+
+        let next = (*at).next;
+        (*ghost).prev = at;
+        (*ghost).next = next;
+        (*at).next = ghost;
+        (*next).prev = ghost;
+        self.validate();
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// 番兵から`next`で辿ると、番兵以外のノードをちょうど`len`個、重複なく通って番兵に戻る事と、
+// 各ノードの`next`の`prev`が自分自身を指している事を確かめる。
+// 繋ぎ変えはすべて`insert_between`などの内部の関数で行うので、`validate`フィーチャーが
+// 有効なときはそれらの最後で呼ばれる。
+impl<T> List<T> {
+    pub fn debug_assert_invariants(&self) {
+        let ghost = self.ghost;
+        assert!(!ghost.is_null(), "the sentinel is null");
+        let mut seen = HashSet::new();
+        let mut node = ghost;
+        for i in 0..self.len + 1 {
+            let next = unsafe { (*node).next };
+            assert!(!next.is_null(), "`next` of {:p} is null", node);
+            assert!(unsafe { (*next).prev } == node, "`prev` of {:p} does not point back to {:p}", next, node);
+            if i < self.len {
+                assert!(next != ghost, "`len` is {}, but the ring has only {} elements", self.len, i);
+                assert!(seen.insert(next), "cycle: {:p} appears twice before the sentinel", next);
+            }
+            node = next;
+        }
+        assert!(node == ghost, "the ring has more than `len` ({}) elements", self.len);
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// 番兵も1つのノードとして書くので、輪になっている様子がそのまま見える。
+// 番兵の次から`len`個だけ辿り、`next`や`prev`が壊れていても止まるようにしている。
+impl<T: Debug> List<T> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("ghost", self.ghost as *const Node<T>, "");
+        unsafe {
+            let ghost = self.ghost as *const Node<T>;
+            dot.node(ghost, "(ghost)");
+            dot.edge(ghost, (*ghost).next as *const Node<T>, "next");
+            dot.edge(ghost, (*ghost).prev as *const Node<T>, "prev");
+            let mut cur = (*ghost).next as *const Node<T>;
+            for _ in 0..self.len {
+                if cur == ghost || cur.is_null() {
+                    break;
+                }
+                dot.node(cur, &format!("{:?}", (*cur).elem.assume_init_ref()));
+                dot.edge(cur, (*cur).next as *const Node<T>, "next");
+                dot.edge(cur, (*cur).prev as *const Node<T>, "prev");
+                cur = (*cur).next;
+            }
+        }
+        dot.finish()
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        List::new()
+    }
+}
+
+impl<T> IntoIterator for List<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut List<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        // 要素の drop が panic しても、残りの要素と番兵は`DropGuard`が解放する。
+        // (その途中でさらに panic すると abort になる。)
+        struct DropGuard<'a, T: 'a>(&'a mut List<T>);
+        impl<'a, T> Drop for DropGuard<'a, T> {
+            fn drop(&mut self) {
+                while self.0.pop_front().is_some() {}
+                // 番兵の`elem`は初期化されていないが、`MaybeUninit`なので drop されない。
+                unsafe { drop(Box::from_raw(self.0.ghost)) };
+            }
+        }
+        let guard = DropGuard(self);
+        while guard.0.pop_front().is_some() {}
+    }
+}
+
+unsafe impl<T: Send> Send for List<T> {}
+unsafe impl<T: Sync> Sync for List<T> {}
+
+impl<'a, T> CursorMut<'a, T> {
+    pub fn current(&mut self) -> Option<&mut T> {
+        if self.cur == self.list.ghost {
+            return None;
+        }
+        unsafe { Some((*self.cur).elem.assume_init_mut()) }
+    }
+
+    // 末尾の次は先頭に戻る。
+    pub fn move_next(&mut self) {
+        unsafe {
+            self.cur = (*self.cur).next;
+            if self.cur == self.list.ghost {
+                self.cur = (*self.cur).next;
+            }
+        }
+    }
+
+    // 先頭の前は末尾に戻る。
+    pub fn move_prev(&mut self) {
+        unsafe {
+            self.cur = (*self.cur).prev;
+            if self.cur == self.list.ghost {
+                self.cur = (*self.cur).prev;
+            }
+        }
+    }
+
+    // リストが空の場合は、挿入した要素が現在位置になる。
+    pub fn insert_after(&mut self, elem: T) {
+        unsafe {
+            let cur = self.cur;
+            let node = self.list.insert_between(cur, (*cur).next, elem);
+            if cur == self.list.ghost {
+                self.cur = node;
+            }
+        }
+    }
+
+    pub fn insert_before(&mut self, elem: T) {
+        unsafe {
+            let cur = self.cur;
+            let node = self.list.insert_between((*cur).prev, cur, elem);
+            if cur == self.list.ghost {
+                self.cur = node;
+            }
+        }
+    }
+
+    // 現在の要素を取り除き、カーソルは次の要素 (末尾なら先頭) に移る。
+    pub fn remove_current(&mut self) -> Option<T> {
+        if self.cur == self.list.ghost {
+            return None;
+        }
+        let node = self.cur;
+        self.move_next();
+        let elem = unsafe { self.list.unlink(node) };
+        if self.list.is_empty() {
+            self.cur = self.list.ghost;
+        }
+        Some(elem)
+    }
+
+    // `other`の全要素を現在の要素の直後に O(1) で繋ぐ。
+    pub fn splice_after(&mut self, other: &mut List<T>) {
+        let was_empty = self.list.is_empty();
+        unsafe { self.list.splice_after(self.cur, other) };
+        if was_empty {
+            self.cur = unsafe { (*self.list.ghost).next };
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.front;
+            self.front = (*node).next;
+            Some((*node).elem.assume_init_ref())
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.back;
+            self.back = (*node).prev;
+            Some((*node).elem.assume_init_ref())
+        }
+    }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.front;
+            self.front = (*node).next;
+            Some(&mut *(ptr::addr_of_mut!((*node).elem) as *mut T))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.back;
+            self.back = (*node).prev;
+            Some(&mut *(ptr::addr_of_mut!((*node).elem) as *mut T))
+        }
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+    use super::List;
+
+    // 番兵から前後どちらに辿っても`len`個の要素を経て番兵に戻り、
+    // 隣り合うノードの`prev`と`next`が互いを指している事を確かめる。
+    fn check<T>(list: &List<T>) {
+        unsafe {
+            let ghost = list.ghost;
+            let mut node = ghost;
+            for _ in 0..list.len + 1 {
+                let next = (*node).next;
+                assert_eq!((*next).prev, node);
+                node = next;
+            }
+            assert_eq!(node, ghost);
+            let mut node = ghost;
+            for _ in 0..list.len + 1 {
+                node = (*node).prev;
+            }
+            assert_eq!(node, ghost);
+        }
+    }
+
+    fn to_vec<T: Clone>(list: &List<T>) -> Vec<T> {
+        list.iter().cloned().collect()
+    }
+
+    #[test]
+    fn basics() {
+        let mut list = List::new();
+        check(&list);
+        assert_eq!(list.pop_front(), None);
+        assert_eq!(list.pop_back(), None);
+        check(&list);
+
+        list.push_back(2);
+        check(&list);
+        list.push_front(1);
+        check(&list);
+        list.push_back(3);
+        check(&list);
+        assert_eq!(list.front(), Some(&1));
+        assert_eq!(list.back(), Some(&3));
+
+        assert_eq!(list.pop_front(), Some(1));
+        check(&list);
+        assert_eq!(list.pop_back(), Some(3));
+        check(&list);
+        *list.front_mut().unwrap() *= 10;
+        assert_eq!(list.back_mut(), Some(&mut 20));
+        assert_eq!(list.pop_back(), Some(20));
+        check(&list);
+        assert_eq!(list.front(), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn rotate() {
+        let mut list = List::new();
+        list.rotate_left(0);
+        check(&list);
+        for i in 0..5 {
+            list.push_back(i);
+        }
+
+        list.rotate_left(2);
+        check(&list);
+        assert_eq!(to_vec(&list), vec![2, 3, 4, 0, 1]);
+        list.rotate_left(4);
+        check(&list);
+        assert_eq!(to_vec(&list), vec![1, 2, 3, 4, 0]);
+        list.rotate_right(1);
+        check(&list);
+        assert_eq!(to_vec(&list), vec![0, 1, 2, 3, 4]);
+        list.rotate_right(5);
+        check(&list);
+        assert_eq!(to_vec(&list), vec![0, 1, 2, 3, 4]);
+        list.rotate_left(5);
+        check(&list);
+        assert_eq!(to_vec(&list), vec![0, 1, 2, 3, 4]);
+
+        list.push_back(5);
+        check(&list);
+        assert_eq!(list.pop_front(), Some(0));
+        check(&list);
+        assert_eq!(to_vec(&list), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rotate_too_far() {
+        let mut list = List::new();
+        list.push_back(1);
+        list.rotate_left(2);
+    }
+
+    #[test]
+    fn append() {
+        let mut a = List::new();
+        let mut b = List::new();
+        a.append(&mut b);
+        check(&a);
+        check(&b);
+
+        b.push_back(1);
+        b.push_back(2);
+        a.append(&mut b);
+        check(&a);
+        check(&b);
+        assert_eq!(to_vec(&a), vec![1, 2]);
+        assert!(b.is_empty());
+
+        b.push_back(3);
+        a.append(&mut b);
+        check(&a);
+        check(&b);
+        assert_eq!(to_vec(&a), vec![1, 2, 3]);
+        b.push_back(4);
+        check(&b);
+        assert_eq!(to_vec(&b), vec![4]);
+    }
+
+    // カーソルが生きている間も、操作のたびに`cursor.list`を借りて確かめる。
+    #[test]
+    fn cursor() {
+        let mut list = List::new();
+        {
+            let mut cursor = list.cursor_front_mut();
+            assert_eq!(cursor.current(), None);
+            cursor.move_next();
+            check(cursor.list);
+            assert_eq!(cursor.current(), None);
+            cursor.insert_after(2);
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 2));
+            cursor.insert_before(1);
+            check(cursor.list);
+            cursor.insert_after(3);
+            check(cursor.list);
+        }
+        assert_eq!(to_vec(&list), vec![1, 2, 3]);
+
+        {
+            let mut cursor = list.cursor_back_mut();
+            assert_eq!(cursor.current(), Some(&mut 3));
+            // 末尾の次は先頭。
+            cursor.move_next();
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 1));
+            cursor.move_prev();
+            check(cursor.list);
+            cursor.move_prev();
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 2));
+            *cursor.current().unwrap() = 20;
+
+            assert_eq!(cursor.remove_current(), Some(20));
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 3));
+            assert_eq!(cursor.remove_current(), Some(3));
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 1));
+        }
+        assert_eq!(to_vec(&list), vec![1]);
+
+        {
+            let mut cursor = list.cursor_front_mut();
+            assert_eq!(cursor.remove_current(), Some(1));
+            check(cursor.list);
+            assert_eq!(cursor.current(), None);
+            assert_eq!(cursor.remove_current(), None);
+            check(cursor.list);
+        }
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn cursor_splice() {
+        let mut list = List::new();
+        let mut other = List::new();
+        other.push_back(10);
+        other.push_back(11);
+        {
+            let mut cursor = list.cursor_front_mut();
+            cursor.splice_after(&mut other);
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 10));
+        }
+        check(&other);
+
+        other.push_back(20);
+        {
+            let mut cursor = list.cursor_front_mut();
+            cursor.splice_after(&mut other);
+            check(cursor.list);
+            cursor.move_prev();
+            check(cursor.list);
+            assert_eq!(cursor.current(), Some(&mut 11));
+        }
+        check(&other);
+        assert_eq!(to_vec(&list), vec![10, 20, 11]);
+    }
+
+    #[test]
+    fn iterators() {
+        let mut list = List::new();
+        for i in 0..4 {
+            list.push_back(i);
+        }
+        // 番兵で止まるので、輪になっていても同じ要素を二度返さない。
+        assert_eq!(list.iter().count(), 4);
+        assert_eq!(list.iter().rev().cloned().collect::<Vec<_>>(), vec![3, 2, 1, 0]);
+
+        for v in &mut list {
+            *v += 1;
+        }
+        let mut sum = 0;
+        for v in &list {
+            sum += *v;
+        }
+        assert_eq!(sum, 10);
+        let mut iter = list.iter_mut();
+        assert_eq!(iter.next_back(), Some(&mut 4));
+        assert_eq!(iter.next(), Some(&mut 1));
+
+        let mut iter = list.into_iter();
+        assert_eq!(iter.next_back(), Some(4));
+        assert_eq!(iter.collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut list = List::new();
+        list.push_back(1);
+        list.push_back(2);
+        let out = list.to_dot();
+        // 番兵も含めた3つのノードが、`next`と`prev`でそれぞれ輪になっている。
+        assert!(out.contains("(ghost)"));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 3);
+        assert_eq!(out.matches(" [label=\"prev\"]").count(), 3);
+        let ghost = format!("\"{:p}\"", list.ghost);
+        assert!(out.contains(&format!("\"ghost\" -> {};", ghost)));
+        assert_eq!(out.matches(&format!("-> {} [label=", ghost)).count(), 2);
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list = List::new();
+        list.debug_assert_invariants();
+        list.push_back(1);
+        list.push_back(2);
+        list.debug_assert_invariants();
+
+        // 末尾の`prev`が番兵を指してしまった状態。
+        unsafe {
+            let last = (*list.ghost).prev;
+            let prev = (*last).prev;
+            (*last).prev = list.ghost;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+            assert!(result.is_err());
+            (*last).prev = prev;
+        }
+        list.len = 3;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.len = 2;
+        list.debug_assert_invariants();
+    }
+}
@@ -0,0 +1,95 @@
+// 各リストの`to_dot`で使う、Graphviz の DOT 形式を組み立てるための小さなヘルパー。
+// ノードの ID にはアドレスをそのまま使う。共有されているノードは同じ ID になるので、
+// 複数のリストから辿っても図の上では1つのノードにまとまる。
+
+// 出力は`dot -Tsvg`などにそのまま渡せる。アドレスは実行ごとに変わるので、
+// テストでは文字列全体ではなく、含まれる行や個数を確かめる。
+
+use std::fmt::Write;
+
+pub(crate) struct Dot {
+    out: String,
+}
+
+impl Dot {
+    pub(crate) fn new() -> Self {
+        Dot { out: String::from("digraph {\n    node [shape=box, fontname=monospace];\n") }
+    }
+
+    // `head`や`tail`のような、リストの構造体が持っているポインタ。
+    // `target`が null なら (空のリストなど) 矢印は書かない。
+    pub(crate) fn root<N: ?Sized>(&mut self, name: &str, target: *const N, label: &str) {
+        let _ = writeln!(self.out, "    \"{}\" [shape=plaintext];", name);
+        if !target.is_null() {
+            let _ = writeln!(self.out, "    \"{}\" -> \"{:p}\"{};", name, target, attr(label));
+        }
+    }
+
+    // ノードのラベルの1行目にはアドレスを入れる。
+    pub(crate) fn node<N: ?Sized>(&mut self, addr: *const N, label: &str) {
+        let label = format!("{:p}\n{}", addr, label);
+        let _ = writeln!(self.out, "    \"{:p}\" [label=\"{}\"];", addr, escape(&label));
+    }
+
+    // `to`が null なら何も書かない。
+    pub(crate) fn edge<N: ?Sized, M: ?Sized>(&mut self, from: *const N, to: *const M, label: &str) {
+        if !to.is_null() {
+            let _ = writeln!(self.out, "    \"{:p}\" -> \"{:p}\"{};", from, to, attr(label));
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        self.out.push_str("}\n");
+        self.out
+    }
+}
+
+fn attr(label: &str) -> String {
+    if label.is_empty() {
+        String::new()
+    } else {
+        format!(" [label=\"{}\"]", escape(label))
+    }
+}
+
+// 要素の`Debug`出力には`"`や`\`が含まれうるので、DOT の文字列として書けるようにする。
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr;
+    use super::Dot;
+
+    #[test]
+    fn output() {
+        let (a, b) = (1, 2);
+        let mut dot = Dot::new();
+        dot.root("head", &a as *const i32, "");
+        dot.root("tail", ptr::null::<i32>(), "");
+        dot.node(&a as *const i32, "\"quoted\\\"");
+        dot.edge(&a as *const i32, &b as *const i32, "next");
+        dot.edge(&a as *const i32, ptr::null::<i32>(), "prev");
+        let out = dot.finish();
+
+        let a = format!("{:p}", &a);
+        let b = format!("{:p}", &b);
+        assert!(out.starts_with("digraph {\n"));
+        assert!(out.ends_with("}\n"));
+        assert!(out.contains(&format!("\"head\" -> \"{}\";", a)));
+        assert!(!out.contains("\"tail\" ->"));
+        assert!(out.contains(&format!("[label=\"{}\\n\\\"quoted\\\\\\\"\"];", a)));
+        assert!(out.contains(&format!("\"{}\" -> \"{}\" [label=\"next\"];", a, b)));
+        assert!(!out.contains("prev"));
+    }
+}
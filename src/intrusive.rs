@@ -0,0 +1,527 @@
+// intrusive な双方向リスト。
+// ノードをリスト側で確保するのではなく、ユーザの構造体に`Link`を埋め込んでもらう。
+// 1つの値に`Link`を複数持たせれば、追加の確保なしに複数のリストへ同時に入れられる。
+
+// `unsafe_deque`と同じく raw pointer で前後を繋ぐが、値の所有権はリストではなく
+// ユーザが持っている。そこでリストには`&'a T`を渡してもらう事にする。
+// こうすると、値がリストに入っている間はムーブも drop もできない事を借用チェッカーが
+// 保証してくれるので、`Pin`を持ち出さなくても安全なインターフェースにできる。
+// 共有参照しか持てないので、`Link`の中身は`Cell`で書き換える。
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use dot::Dot;
+
+// 各リストに振る一意な番号。0 は「どのリストにも入っていない」を表す。
+static NEXT_LIST_ID: AtomicUsize = AtomicUsize::new(1);
+
+pub struct Link {
+    // 前後の「値」へのポインタ。`Link`ではなく値そのものを指すようにしておくと、
+    // `Link`から値へ戻るためのオフセット計算が要らない。
+    prev: Cell<*const ()>,
+    next: Cell<*const ()>,
+    owner: Cell<usize>,
+}
+
+impl Link {
+    pub const fn new() -> Self {
+        Link {
+            prev: Cell::new(ptr::null()),
+            next: Cell::new(ptr::null()),
+            owner: Cell::new(0),
+        }
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.owner.get() != 0
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link::new()
+    }
+}
+
+// 値を clone しても、clone したものはどのリストにも入っていない。
+impl Clone for Link {
+    fn clone(&self) -> Self {
+        Link::new()
+    }
+}
+
+impl fmt::Debug for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Link").field("linked", &self.is_linked()).finish()
+    }
+}
+
+/// 値からリスト用の`Link`を取り出す方法を決める。
+/// 通常は`intrusive_adapter!`で定義する。
+///
+/// # Safety
+///
+/// `link`は同じ値に対して常に同じ`Link` (値に埋め込まれたフィールド) を返さなければならない。
+/// 違う`Link`を返すと、リストが既に無効な値へのポインタを辿ってしまう可能性がある。
+pub unsafe trait Adapter {
+    type Value;
+    fn link(value: &Self::Value) -> &Link;
+}
+
+/// `intrusive_adapter!(RunQueue = Task { run_link });`のように、
+/// 構造体のフィールドを`Link`として使う`Adapter`を定義する。
+#[macro_export]
+macro_rules! intrusive_adapter {
+    ($(#[$attr:meta])* $vis:vis $name:ident = $value:ty { $field:ident }) => {
+        $(#[$attr])*
+        $vis struct $name;
+
+        unsafe impl $crate::intrusive::Adapter for $name {
+            type Value = $value;
+            fn link(value: &$value) -> &$crate::intrusive::Link {
+                &value.$field
+            }
+        }
+    };
+}
+
+pub struct List<'a, A: Adapter>
+    where A::Value: 'a
+{
+    head: *const A::Value,
+    tail: *const A::Value,
+    len: usize,
+    id: usize,
+    _marker: PhantomData<(&'a A::Value, A)>,
+}
+
+// `next`と`next_back`はリストの中を指しているので、リストを借用している間しか辿れない。
+// 値そのものは`'a`の間生きているが、`'a`に結び付けると`Iter`を持ったまま`clear`などができてしまい、
+// 外された値や null を辿る事になる。
+pub struct Iter<'s, A: Adapter>
+    where A::Value: 's
+{
+    next: *const A::Value,
+    next_back: *const A::Value,
+    len: usize,
+    _marker: PhantomData<&'s A::Value>,
+}
+
+// `p`はリストに入っている (= `'a`の間は生きている) 値を指していなければならない。
+unsafe fn link_of<'b, A: Adapter>(p: *const A::Value) -> &'b Link
+    where A::Value: 'b
+{
+    A::link(&*p)
+}
+
+fn erase<T>(p: *const T) -> *const () {
+    p as *const ()
+}
+
+impl<'a, A: Adapter> List<'a, A> {
+    pub fn new() -> Self {
+        List {
+            head: ptr::null(),
+            tail: ptr::null(),
+            len: 0,
+            id: NEXT_LIST_ID.fetch_add(1, Ordering::Relaxed),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // `Link`に記録された持ち主を見るだけなので O(1)。
+    pub fn contains(&self, value: &A::Value) -> bool {
+        A::link(value).owner.get() == self.id
+    }
+
+    pub fn front(&self) -> Option<&'a A::Value> {
+        unsafe { self.head.as_ref() }
+    }
+
+    pub fn back(&self) -> Option<&'a A::Value> {
+        unsafe { self.tail.as_ref() }
+    }
+
+    // 既にどこかのリストに入っている`Link`を使おうとした場合は panic する。
+    pub fn push_front(&mut self, value: &'a A::Value) {
+        let link = A::link(value);
+        assert!(!link.is_linked(), "the link is already in a list");
+
+        let raw: *const A::Value = value;
+        link.owner.set(self.id);
+        link.prev.set(ptr::null());
+        link.next.set(erase(self.head));
+        if self.head.is_null() {
+            self.tail = raw;
+        } else {
+            unsafe { link_of::<A>(self.head).prev.set(erase(raw)) };
+        }
+        self.head = raw;
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn push_back(&mut self, value: &'a A::Value) {
+        let link = A::link(value);
+        assert!(!link.is_linked(), "the link is already in a list");
+
+        let raw: *const A::Value = value;
+        link.owner.set(self.id);
+        link.next.set(ptr::null());
+        link.prev.set(erase(self.tail));
+        if self.tail.is_null() {
+            self.head = raw;
+        } else {
+            unsafe { link_of::<A>(self.tail).next.set(erase(raw)) };
+        }
+        self.tail = raw;
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn pop_front(&mut self) -> Option<&'a A::Value> {
+        let head = self.front()?;
+        unsafe { self.unlink(head) };
+        Some(head)
+    }
+
+    pub fn pop_back(&mut self) -> Option<&'a A::Value> {
+        let tail = self.back()?;
+        unsafe { self.unlink(tail) };
+        Some(tail)
+    }
+
+    // このリストに入っていない値を渡した場合は何もせず`false`を返す。
+    pub fn remove(&mut self, value: &A::Value) -> bool {
+        if !self.contains(value) {
+            return false;
+        }
+        unsafe { self.unlink(value) };
+        true
+    }
+
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    /// `Iter`はリストを借用するので、辿っている間はリストを変更できない。
+    ///
+    /// ```compile_fail
+    /// #[macro_use] extern crate lists;
+    /// use lists::intrusive::{Link, List};
+    ///
+    /// struct Task { link: Link }
+    /// intrusive_adapter!(Adapter = Task { link });
+    ///
+    /// fn main() {
+    ///     let task = Task { link: Link::new() };
+    ///     let mut list = List::<Adapter>::new();
+    ///     list.push_back(&task);
+    ///     let mut iter = list.iter();
+    ///     list.clear();
+    ///     iter.next();
+    /// }
+    /// ```
+    pub fn iter(&self) -> Iter<'_, A> {
+        Iter {
+            next: self.head,
+            next_back: self.tail,
+            len: self.len,
+            _marker: PhantomData,
+        }
+    }
+
+    // `value`はこのリストに入っていなければならない。
+    unsafe fn unlink(&mut self, value: &A::Value) {
+        let link = A::link(value);
+        let prev = link.prev.get() as *const A::Value;
+        let next = link.next.get() as *const A::Value;
+
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            link_of::<A>(prev).next.set(erase(next));
+        }
+        if next.is_null() {
+            self.tail = prev;
+        } else {
+            link_of::<A>(next).prev.set(erase(prev));
+        }
+
+        link.prev.set(ptr::null());
+        link.next.set(ptr::null());
+        link.owner.set(0);
+        self.len -= 1;
+        self.validate();
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// `head`から`next`で辿った値が重複せずちょうど`len`個で`tail`に着く事、
+// 隣り合う値の`prev`と`next`が互いを指している事、どの`Link`の持ち主もこのリストである事を確かめる。
+// `validate`フィーチャーを有効にすると、リストを変更する操作のたびに呼ばれる。
+impl<'a, A: Adapter> List<'a, A> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut prev: *const () = ptr::null();
+        let mut cur = erase(self.head);
+        while !cur.is_null() {
+            assert!(seen.insert(cur), "cycle: {:p} is reachable twice from `head`", cur);
+            assert!(seen.len() <= self.len, "more than `len` ({}) values are reachable from `head`", self.len);
+            let link = unsafe { link_of::<A>(cur as *const A::Value) };
+            assert_eq!(link.owner.get(), self.id, "{:p} is linked but owned by another list", cur);
+            assert!(link.prev.get() == prev, "`prev` of {:p} is {:p}, expected {:p}", cur, link.prev.get(), prev);
+            prev = cur;
+            cur = link.next.get();
+        }
+        assert_eq!(seen.len(), self.len, "`len` does not match the number of linked values");
+        assert!(erase(self.tail) == prev, "`tail` is {:p}, but the last value is {:p}", self.tail, prev);
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// リンクの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// ノードはユーザの値そのものなので、値のアドレスと`Debug`出力を書く。
+impl<'a, A: Adapter> List<'a, A>
+    where A::Value: fmt::Debug
+{
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", self.head, "");
+        dot.root("tail", self.tail, "");
+        for value in self.iter() {
+            let link = A::link(value);
+            dot.node(value as *const A::Value, &format!("{:?}", value));
+            dot.edge(value as *const A::Value, link.next.get(), "next");
+            dot.edge(value as *const A::Value, link.prev.get(), "prev");
+        }
+        dot.finish()
+    }
+}
+
+impl<'a, A: Adapter> Default for List<'a, A> {
+    fn default() -> Self {
+        List::new()
+    }
+}
+
+// リストが消えたあとも`Link`が「リストに入っている」ままだと、
+// その値は二度とリストに入れられなくなるので、全部外しておく。
+impl<'a, A: Adapter> Drop for List<'a, A> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<'s, A: Adapter> Iterator for Iter<'s, A> {
+    type Item = &'s A::Value;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let value = &*self.next;
+            self.next = A::link(value).next.get() as *const A::Value;
+            Some(value)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'s, A: Adapter> DoubleEndedIterator for Iter<'s, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let value = &*self.next_back;
+            self.next_back = A::link(value).prev.get() as *const A::Value;
+            Some(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+    use super::{Link, List};
+
+    #[derive(Debug)]
+    struct Task {
+        id: u32,
+        run_link: Link,
+        lru_link: Link,
+    }
+
+    impl Task {
+        fn new(id: u32) -> Self {
+            Task { id, run_link: Link::new(), lru_link: Link::new() }
+        }
+    }
+
+    intrusive_adapter!(RunQueue = Task { run_link });
+    intrusive_adapter!(Lru = Task { lru_link });
+
+    fn ids<'a, I: Iterator<Item = &'a Task>>(iter: I) -> Vec<u32> {
+        iter.map(|t| t.id).collect()
+    }
+
+    #[test]
+    fn basics() {
+        let tasks: Vec<Task> = (0..4).map(Task::new).collect();
+        let mut list: List<RunQueue> = List::new();
+        assert_eq!(list.pop_front().map(|t| t.id), None);
+
+        list.push_back(&tasks[1]);
+        list.push_back(&tasks[2]);
+        list.push_front(&tasks[0]);
+        assert_eq!(list.len(), 3);
+        assert_eq!(ids(list.iter()), vec![0, 1, 2]);
+        assert_eq!(ids(list.iter().rev()), vec![2, 1, 0]);
+
+        assert_eq!(list.pop_front().map(|t| t.id), Some(0));
+        assert_eq!(list.pop_back().map(|t| t.id), Some(2));
+        assert!(!tasks[0].run_link.is_linked());
+
+        list.push_back(&tasks[3]);
+        assert_eq!(list.front().map(|t| t.id), Some(1));
+        assert_eq!(list.back().map(|t| t.id), Some(3));
+        assert_eq!(list.pop_back().map(|t| t.id), Some(3));
+        assert_eq!(list.pop_back().map(|t| t.id), Some(1));
+        assert_eq!(list.pop_back().map(|t| t.id), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn remove() {
+        let tasks: Vec<Task> = (0..4).map(Task::new).collect();
+        let mut list: List<RunQueue> = List::new();
+        let mut other: List<RunQueue> = List::new();
+        for t in &tasks[..3] {
+            list.push_back(t);
+        }
+        other.push_back(&tasks[3]);
+
+        assert!(!list.remove(&tasks[3]));
+        assert!(list.remove(&tasks[1]));
+        assert!(!list.remove(&tasks[1]));
+        assert_eq!(ids(list.iter()), vec![0, 2]);
+
+        assert!(list.remove(&tasks[2]));
+        assert_eq!(list.back().map(|t| t.id), Some(0));
+        assert!(list.remove(&tasks[0]));
+        assert!(list.is_empty());
+        assert_eq!(list.front().map(|t| t.id), None);
+
+        assert!(other.contains(&tasks[3]));
+        assert_eq!(ids(other.iter()), vec![3]);
+    }
+
+    #[test]
+    fn multiple_lists() {
+        let tasks: Vec<Task> = (0..3).map(Task::new).collect();
+        let mut run: List<RunQueue> = List::new();
+        let mut lru: List<Lru> = List::new();
+        for t in &tasks {
+            run.push_back(t);
+            lru.push_front(t);
+        }
+        assert_eq!(ids(run.iter()), vec![0, 1, 2]);
+        assert_eq!(ids(lru.iter()), vec![2, 1, 0]);
+
+        // 片方のリストから外しても、もう片方には影響しない。
+        run.remove(&tasks[1]);
+        assert_eq!(ids(run.iter()), vec![0, 2]);
+        assert_eq!(ids(lru.iter()), vec![2, 1, 0]);
+
+        lru.remove(&tasks[1]);
+        lru.push_front(&tasks[1]);
+        assert_eq!(ids(lru.iter()), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn relink_after_drop() {
+        let task = Task::new(0);
+        {
+            let mut list: List<RunQueue> = List::new();
+            list.push_back(&task);
+            assert!(task.run_link.is_linked());
+        }
+        assert!(!task.run_link.is_linked());
+
+        let mut list: List<RunQueue> = List::new();
+        list.push_back(&task);
+        assert!(list.contains(&task));
+    }
+
+    #[test]
+    #[should_panic]
+    fn double_push() {
+        let task = Task::new(0);
+        let mut a: List<RunQueue> = List::new();
+        let mut b: List<RunQueue> = List::new();
+        a.push_back(&task);
+        b.push_back(&task);
+    }
+
+    #[test]
+    fn invariants() {
+        let (a, b) = (Task::new(1), Task::new(2));
+        let mut list: List<RunQueue> = List::new();
+        list.push_back(&a);
+        list.push_back(&b);
+        list.debug_assert_invariants();
+
+        // 持ち主の記録が別のリストになっている状態。
+        let owner = b.run_link.owner.replace(usize::MAX);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        b.run_link.owner.set(owner);
+
+        // `tail`が末尾ではない値を指している状態。
+        list.tail = &a;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.tail = &b;
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn to_dot() {
+        let tasks: Vec<Task> = (0..2).map(Task::new).collect();
+        let mut list: List<RunQueue> = List::new();
+        for task in &tasks {
+            list.push_back(task);
+        }
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert!(out.contains("\"tail\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert_eq!(out.matches(" [label=\"prev\"]").count(), 1);
+        // ノードは要素そのものなので、ラベルには`Task`全体が出る。
+        assert_eq!(out.matches("run_link: Link { linked: true }").count(), 2);
+    }
+}
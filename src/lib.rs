@@ -1,5 +1,28 @@
+#[cfg(feature = "async")]
+extern crate futures_core;
+
 pub mod bad_stack;
 pub mod ok_stack;
 pub mod persistent_stack;
 pub mod bad_safe_deque;
 pub mod unsafe_deque;
+pub mod node_alloc;
+#[macro_use]
+pub mod intrusive;
+pub mod lru;
+pub mod skip_list;
+pub mod unrolled;
+// 実験的なモジュール。インターフェースが予告なく変わりうるので、ドキュメントには載せない。
+#[doc(hidden)]
+pub mod xor_deque;
+pub mod circular;
+pub mod random_access;
+pub mod zipper;
+pub mod channel;
+pub mod priority_queue;
+pub mod atomic_list;
+pub mod treiber_stack;
+mod par;
+mod dot;
+#[cfg(feature = "async")]
+pub mod async_queue;
@@ -0,0 +1,298 @@
+// `bad_safe_deque::List`を使った LRU キャッシュ。
+// リストの先頭が最近使われたもの、末尾が最も長く使われていないもの。
+// `HashMap`にはキーごとにノードの`NodeHandle`を持たせておき、
+// 参照されたノードをリストの途中から先頭へ O(1) で付け替える。
+
+use std::borrow::Borrow;
+use std::cell::{Ref, RefMut};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::mem;
+use bad_safe_deque::{List, NodeHandle};
+
+pub struct LruCache<K, V> {
+    map: HashMap<K, NodeHandle<(K, V)>>,
+    list: List<(K, V)>,
+    cap: usize,
+    on_evict: Option<Box<dyn FnMut(K, V)>>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(cap: usize) -> Self {
+        LruCache {
+            map: HashMap::new(),
+            list: List::new(),
+            cap,
+            on_evict: None,
+        }
+    }
+
+    // 容量を超えて追い出されたときに呼ばれる。`pop_lru`などで明示的に取り出した場合は呼ばれない。
+    pub fn on_evict<F: FnMut(K, V) + 'static>(&mut self, f: F) {
+        self.on_evict = Some(Box::new(f));
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    pub fn contains_key<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.map.contains_key(key)
+    }
+
+    // 参照したエントリは最近使われたものとして先頭に移る。
+    // `bad_safe_deque`と同じく、`&V`ではなく`Ref`を返す。
+    pub fn get<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<Ref<'_, V>>
+        where K: Borrow<Q>
+    {
+        let handle = self.map.get(key)?;
+        self.list.move_to_front(handle);
+        self.list.get(handle).map(|e| Ref::map(e, |e| &e.1))
+    }
+
+    pub fn get_mut<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<RefMut<'_, V>>
+        where K: Borrow<Q>
+    {
+        let handle = self.map.get(key)?;
+        self.list.move_to_front(handle);
+        self.list.get_mut(handle).map(|e| RefMut::map(e, |e| &mut e.1))
+    }
+
+    // `get`と違い、順番は変えない。
+    pub fn peek<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<Ref<'_, V>>
+        where K: Borrow<Q>
+    {
+        let handle = self.map.get(key)?;
+        self.list.get(handle).map(|e| Ref::map(e, |e| &e.1))
+    }
+
+    pub fn peek_lru(&self) -> Option<Ref<'_, (K, V)>> {
+        self.list.peek_back()
+    }
+
+    // 既にあるキーなら値を置き換えて古い値を返す。
+    pub fn put(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(mut old) = self.get_mut(&key) {
+            return Some(mem::replace(&mut *old, value));
+        }
+
+        let handle = self.list.push_front_with_handle((key.clone(), value));
+        self.map.insert(key, handle);
+        self.evict_to(self.cap);
+        None
+    }
+
+    pub fn pop<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        let handle = self.map.remove(key)?;
+        self.list.remove(&handle).map(|(_, v)| v)
+    }
+
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        let (k, v) = self.list.pop_back()?;
+        self.map.remove(&k);
+        Some((k, v))
+    }
+
+    // 小さくした場合は、はみ出した分を古い順に追い出す。
+    pub fn resize(&mut self, cap: usize) {
+        self.cap = cap;
+        self.evict_to(cap);
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        while self.list.pop_front().is_some() {}
+    }
+
+    fn evict_to(&mut self, cap: usize) {
+        while self.len() > cap {
+            let (k, v) = self.pop_lru().unwrap();
+            if let Some(ref mut on_evict) = self.on_evict {
+                on_evict(k, v);
+            }
+        }
+    }
+}
+
+// 中のリストを DOT 形式で出力する (デバッグ用)。先頭が最近使われたもの。
+// `HashMap`がノードごとに`NodeHandle`を持っているので、各ノードの weak count は 1 つ多くなる。
+impl<K: Debug, V: Debug> LruCache<K, V> {
+    pub fn to_dot(&self) -> String {
+        self.list.to_dot()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::LruCache;
+
+    #[test]
+    fn basics() {
+        let mut cache = LruCache::new(2);
+        assert_eq!(cache.put("a", 1), None);
+        assert_eq!(cache.put("b", 2), None);
+        assert_eq!(*cache.get("a").unwrap(), 1);
+
+        // "b" が最も長く使われていない。
+        assert_eq!(cache.put("c", 3), None);
+        assert!(!cache.contains_key("b"));
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.put("a", 10), Some(1));
+        *cache.get_mut("c").unwrap() += 1;
+        assert_eq!(cache.pop_lru(), Some(("a", 10)));
+        assert_eq!(cache.pop("c"), Some(4));
+        assert_eq!(cache.pop("c"), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.pop_lru(), None);
+    }
+
+    #[test]
+    fn peek() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert_eq!(*cache.peek(&1).unwrap(), "one");
+        assert_eq!(cache.peek_lru().map(|e| e.0), Some(1));
+
+        // peek では順番が変わらないので 1 が追い出される。
+        cache.put(3, "three");
+        assert!(cache.peek(&1).is_none());
+    }
+
+    #[test]
+    fn eviction_callback() {
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let mut cache = LruCache::new(3);
+        {
+            let evicted = evicted.clone();
+            cache.on_evict(move |k, v| evicted.borrow_mut().push((k, v)));
+        }
+        for i in 0..5 {
+            cache.put(i, i * 10);
+        }
+        assert_eq!(*evicted.borrow(), vec![(0, 0), (1, 10)]);
+
+        cache.get(&2);
+        cache.resize(1);
+        assert_eq!(*evicted.borrow(), vec![(0, 0), (1, 10), (3, 30), (4, 40)]);
+        assert_eq!(cache.capacity(), 1);
+        assert_eq!(*cache.peek(&2).unwrap(), 20);
+
+        // 明示的に取り出した場合は呼ばれない。
+        assert_eq!(cache.pop_lru(), Some((2, 20)));
+        assert_eq!(evicted.borrow().len(), 4);
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache = LruCache::new(0);
+        cache.put(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    // `Vec`を先頭が最近使われたものになるように並べるだけの素朴な実装と比べる。
+    struct Model {
+        entries: Vec<(u8, u32)>,
+        cap: usize,
+        evicted: Vec<(u8, u32)>,
+    }
+
+    impl Model {
+        fn touch(&mut self, key: u8) -> Option<u32> {
+            let pos = self.entries.iter().position(|e| e.0 == key)?;
+            let entry = self.entries.remove(pos);
+            self.entries.insert(0, entry);
+            Some(entry.1)
+        }
+
+        fn put(&mut self, key: u8, value: u32) -> Option<u32> {
+            if let Some(pos) = self.entries.iter().position(|e| e.0 == key) {
+                let old = self.entries.remove(pos).1;
+                self.entries.insert(0, (key, value));
+                return Some(old);
+            }
+            self.entries.insert(0, (key, value));
+            self.shrink();
+            None
+        }
+
+        fn shrink(&mut self) {
+            while self.entries.len() > self.cap {
+                let e = self.entries.pop().unwrap();
+                self.evicted.push(e);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_model() {
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let mut cache = LruCache::new(8);
+        {
+            let evicted = evicted.clone();
+            cache.on_evict(move |k, v| evicted.borrow_mut().push((k, v)));
+        }
+        let mut model = Model { entries: Vec::new(), cap: 8, evicted: Vec::new() };
+
+        let mut seed: u32 = 12345;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) & 0x7fff
+        };
+        for i in 0..5000 {
+            let key = (rand() % 16) as u8;
+            match rand() % 7 {
+                0 | 1 => assert_eq!(cache.put(key, i), model.put(key, i)),
+                2 => assert_eq!(cache.get(&key).map(|v| *v), model.touch(key)),
+                3 => {
+                    let expected = model.entries.iter().find(|e| e.0 == key).map(|e| e.1);
+                    assert_eq!(cache.peek(&key).map(|v| *v), expected);
+                }
+                4 => {
+                    let pos = model.entries.iter().position(|e| e.0 == key);
+                    let expected = pos.map(|p| model.entries.remove(p).1);
+                    assert_eq!(cache.pop(&key), expected);
+                }
+                5 => assert_eq!(cache.pop_lru(), model.entries.pop()),
+                _ => {
+                    let cap = (rand() % 10) as usize;
+                    cache.resize(cap);
+                    model.cap = cap;
+                    model.shrink();
+                }
+            }
+            assert_eq!(cache.len(), model.entries.len());
+            assert_eq!(cache.peek_lru().map(|e| *e), model.entries.last().cloned());
+        }
+        assert_eq!(*evicted.borrow(), model.evicted);
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "a");
+        cache.put(2, "b");
+        let out = cache.to_dot();
+        // 最近使った`2`が先頭。`Weak`の数には`map`が持っているハンドルの分も入る。
+        assert!(out.contains("\\n(2, \\\"b\\\")\\nstrong=1 weak=2\""));
+        assert!(out.contains("\\n(1, \\\"a\\\")\\nstrong=2 weak=1\""));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert_eq!(out.matches(" [label=\"prev\"]").count(), 1);
+    }
+}
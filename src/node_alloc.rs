@@ -0,0 +1,222 @@
+// ノードのメモリをどこから確保するかを差し替えるための仕組み。
+// `std::alloc::Allocator`はまだ nightly 限定なので、crate 内に小さな trait を用意する。
+// デフォルトは`Global` (グローバルアロケータ) なので、
+// 型引数を省略した既存のコードはそのままコンパイルできる。
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl ::std::error::Error for AllocError {}
+
+/// # Safety
+///
+/// `allocate`が返したポインタは、同じアロケータ (または その clone) に同じ`layout`で
+/// `deallocate`されるまで有効でなければならない。
+/// arena のように`deallocate`で何もしない実装も許される。
+pub unsafe trait NodeAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// # Safety
+    ///
+    /// `ptr`はこのアロケータの`allocate`が`layout`で返したものでなければならない。
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Global;
+
+unsafe impl NodeAllocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            // サイズ0の確保はグローバルアロケータに渡せない (未定義動作になる)。
+            return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+        }
+        NonNull::new(unsafe { alloc::alloc(layout) }).ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            alloc::dealloc(ptr.as_ptr(), layout);
+        }
+    }
+}
+
+// 呼び出し側の arena を借用して渡せるようにする。
+unsafe impl<A: NodeAllocator + ?Sized> NodeAllocator for &A {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+}
+
+// `Box<T, A>`の代わり。各ノードが自分を確保したアロケータを持つので、
+// drop されたときに同じアロケータへメモリを返せる。
+// `Global`はサイズ0なので、`Box<T>`と比べてノードが大きくなることはない。
+pub struct NodeBox<T, A: NodeAllocator> {
+    ptr: NonNull<T>,
+    alloc: A,
+    _marker: PhantomData<T>,
+}
+
+impl<T, A: NodeAllocator> NodeBox<T, A> {
+    pub fn new_in(value: T, alloc: A) -> Self {
+        match NodeBox::try_new_in(value, alloc) {
+            Ok(b) => b,
+            Err(_) => alloc::handle_alloc_error(Layout::new::<T>()),
+        }
+    }
+
+    // 確保に失敗した場合は値をそのまま返す。
+    pub fn try_new_in(value: T, alloc: A) -> Result<Self, (AllocError, T)> {
+        let ptr = match alloc.allocate(Layout::new::<T>()) {
+            Ok(ptr) => ptr.cast::<T>(),
+            Err(e) => return Err((e, value)),
+        };
+        unsafe { ptr::write(ptr.as_ptr(), value) };
+        Ok(NodeBox { ptr, alloc, _marker: PhantomData })
+    }
+
+    pub fn into_inner(b: Self) -> T {
+        // 値を読み出したあとに`Drop`が走らないようにし、アロケータだけを取り出す。
+        let b = mem::ManuallyDrop::new(b);
+        let value = unsafe { ptr::read(b.ptr.as_ptr()) };
+        let alloc = unsafe { ptr::read(&b.alloc) };
+        unsafe { alloc.deallocate(b.ptr.cast(), Layout::new::<T>()) };
+        value
+    }
+
+    pub fn allocator(b: &Self) -> &A {
+        &b.alloc
+    }
+}
+
+impl<T, A: NodeAllocator> Deref for NodeBox<T, A> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, A: NodeAllocator> DerefMut for NodeBox<T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T, A: NodeAllocator> Drop for NodeBox<T, A> {
+    fn drop(&mut self) {
+        // 値の drop が panic してもメモリは返すようにする。
+        struct Dealloc<'a, T, A: NodeAllocator + 'a>(&'a NodeBox<T, A>);
+        impl<'a, T, A: NodeAllocator> Drop for Dealloc<'a, T, A> {
+            fn drop(&mut self) {
+                unsafe { self.0.alloc.deallocate(self.0.ptr.cast(), Layout::new::<T>()) };
+            }
+        }
+        let guard = Dealloc(self);
+        unsafe { ptr::drop_in_place(guard.0.ptr.as_ptr()) };
+    }
+}
+
+// `Box<T>`と同じく、値はヒープ上にあって`NodeBox`を動かしても動かないので、
+// `T`によらず`Unpin`にしてよい。
+impl<T, A: NodeAllocator + Unpin> Unpin for NodeBox<T, A> {}
+
+unsafe impl<T: Send, A: NodeAllocator + Send> Send for NodeBox<T, A> {}
+unsafe impl<T: Sync, A: NodeAllocator + Sync> Sync for NodeBox<T, A> {}
+
+#[cfg(test)]
+pub mod test {
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use super::{AllocError, Global, NodeAllocator, NodeBox};
+
+    // 確保と解放の回数を数えるだけのアロケータ。
+    // `limit`を超えた確保は失敗させる。
+    #[derive(Default)]
+    pub struct CountingAlloc {
+        pub allocs: Cell<usize>,
+        pub deallocs: Cell<usize>,
+        pub limit: Cell<Option<usize>>,
+    }
+
+    impl CountingAlloc {
+        pub fn live(&self) -> usize {
+            self.allocs.get() - self.deallocs.get()
+        }
+    }
+
+    unsafe impl NodeAllocator for CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            if let Some(limit) = self.limit.get() {
+                if self.allocs.get() >= limit {
+                    return Err(AllocError);
+                }
+            }
+            self.allocs.set(self.allocs.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.deallocs.set(self.deallocs.get() + 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    // drop された回数を数え、`1`が true なら drop の途中で panic する。
+    // 要素の drop が panic した時の振る舞いを確かめるテストで共有する。
+    pub struct PanicOnDrop<'a>(pub &'a Cell<usize>, pub bool);
+
+    impl<'a> Drop for PanicOnDrop<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+            if self.1 {
+                panic!("drop panicked");
+            }
+        }
+    }
+
+    #[test]
+    fn node_box() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut b = NodeBox::new_in(String::from("a"), &alloc);
+            b.push('b');
+            assert_eq!(&*b, "ab");
+            assert_eq!(alloc.live(), 1);
+
+            let b2 = NodeBox::new_in(vec![1, 2], &alloc);
+            assert_eq!(NodeBox::into_inner(b2), vec![1, 2]);
+            assert_eq!(alloc.live(), 1);
+        }
+        assert_eq!(alloc.allocs.get(), 2);
+        assert_eq!(alloc.live(), 0);
+    }
+
+    #[test]
+    fn failing_alloc() {
+        let alloc = CountingAlloc::default();
+        alloc.limit.set(Some(0));
+        let result = NodeBox::try_new_in(7, &alloc);
+        match result {
+            Err((AllocError, v)) => assert_eq!(v, 7),
+            Ok(_) => panic!("allocation should fail"),
+        }
+    }
+}
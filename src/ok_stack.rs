@@ -1,19 +1,37 @@
 // second.rs
 
-pub struct List<T> {
-    head: Link<T>,
+use std::array;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ptr;
+use dot::Dot;
+use node_alloc::{AllocError, Global, NodeAllocator, NodeBox};
+use par;
+
+// ノードは`Box`の代わりに`NodeBox`で持ち、`A`から確保する。
+// `A`を省略すると`Global`になるので、`List<T>`は今まで通り使える。
+pub struct List<T, A: NodeAllocator = Global> {
+    head: Link<T, A>,
+    alloc: A,
 }
 
-type Link<T> = Option<Box<Node<T>>>;
+type Link<T, A> = Option<NodeBox<Node<T, A>, A>>;
 
-struct Node<T> {
+struct Node<T, A: NodeAllocator> {
     elem: T,
-    next: Link<T>,
+    next: Link<T, A>,
 }
 
 impl<T> List<T> {
     pub fn new() -> Self {
-        List { head: None }
+        List::new_in(Global)
+    }
+}
+
+impl<T, A: NodeAllocator + Clone> List<T, A> {
+    pub fn new_in(alloc: A) -> Self {
+        List { head: None, alloc }
     }
 
     pub fn push(&mut self, elem: T) {
@@ -21,15 +39,39 @@ impl<T> List<T> {
             elem: elem,
             next: self.head.take(),
         };
-        self.head = Some(Box::new(new_node));
+        self.head = Some(NodeBox::new_in(new_node, self.alloc.clone()));
+        self.validate();
     }
 
+    // 確保に失敗した場合は abort せず、`elem`をそのまま返す。リストは変わらない。
+    pub fn try_push(&mut self, elem: T) -> Result<(), (AllocError, T)> {
+        let new_node = Node {
+            elem,
+            next: self.head.take(),
+        };
+        match NodeBox::try_new_in(new_node, self.alloc.clone()) {
+            Ok(node) => {
+                self.head = Some(node);
+                self.validate();
+                Ok(())
+            }
+            Err((e, node)) => {
+                self.head = node.next;
+                Err((e, node.elem))
+            }
+        }
+    }
+}
+
+impl<T, A: NodeAllocator> List<T, A> {
     pub fn pop(&mut self) -> Option<T> {
-        self.head.take().map(|node| {
-            let node = *node;
+        let elem = self.head.take().map(|node| {
+            let node = NodeBox::into_inner(node);
             self.head = node.next;
             node.elem
-        })
+        });
+        self.validate();
+        elem
     }
 
     pub fn peek(&self) -> Option<&T> {
@@ -45,24 +87,31 @@ impl<T> List<T> {
     }
 }
 
-impl<T> Drop for List<T> {
+impl<T, A: NodeAllocator> Drop for List<T, A> {
     fn drop(&mut self) {
-        let mut cur_link = self.head.take();
-        while let Some(mut boxed_node) = cur_link {
-            cur_link = boxed_node.next.take();
+        // 要素の drop が panic しても、残りの要素は`DropGuard`が drop し続ける。
+        // (その途中でさらに panic すると abort になる。)
+        struct DropGuard<'a, T: 'a, A: NodeAllocator + 'a>(&'a mut List<T, A>);
+        impl<'a, T, A: NodeAllocator> Drop for DropGuard<'a, T, A> {
+            fn drop(&mut self) {
+                while self.0.pop().is_some() {}
+            }
         }
+        // `pop`はノードを1つずつ外すので、再帰的な drop にもならない。
+        let guard = DropGuard(self);
+        while guard.0.pop().is_some() {}
     }
 }
 
-pub struct IntoIter<T>(List<T>);
+pub struct IntoIter<T, A: NodeAllocator = Global>(List<T, A>);
 
-impl<T> List<T> {
-    pub fn into_iter(self) -> IntoIter<T> {
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn into_iter(self) -> IntoIter<T, A> {
         IntoIter(self)
     }
 }
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, A: NodeAllocator> Iterator for IntoIter<T, A> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
         self.0.pop()
@@ -76,21 +125,28 @@ impl<T> Iterator for IntoIter<T> {
 
 // Iter is generic over *some* lifetime, it doesn't care.
 // ただしIter に保持される T 型の値も同じlifetimeを持つ必要がある。
-pub struct Iter<'a, T: 'a> {
-    next: Option<&'a Node<T>>,
+pub struct Iter<'a, T: 'a, A: NodeAllocator + 'a = Global> {
+    next: Option<&'a Node<T, A>>,
 }
 
-impl<T> List<T> {
+impl<T, A: NodeAllocator> List<T, A> {
     // `&self`と同じlifetimeの`Iter`を作る。
     // これにより、`Iter`の生存中は`&self`も生存している事が保証される。
     // ただし`self`のライフタイムを使う場合、記述は省略可能。
     // pub fn iter(&'a self) -> Iter<'a, T> {
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<T, A> {
         Iter { next: self.head.as_ref().map(|node| &**node) }
     }
 }
 
-impl<'a, T> Iterator for Iter<'a, T> {
+// ノードへの参照を持っているだけなので、`T: Clone`でなくても複製できる。
+impl<'a, T, A: NodeAllocator> Clone for Iter<'a, T, A> {
+    fn clone(&self) -> Self {
+        Iter { next: self.next }
+    }
+}
+
+impl<'a, T, A: NodeAllocator> Iterator for Iter<'a, T, A> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
         self.next.map(|node| {
@@ -100,17 +156,17 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
-pub struct IterMut<'a, T: 'a> {
-    next: Option<&'a mut Node<T>>,
+pub struct IterMut<'a, T: 'a, A: NodeAllocator + 'a = Global> {
+    next: Option<&'a mut Node<T, A>>,
 }
 
-impl<T> List<T> {
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn iter_mut(&mut self) -> IterMut<T, A> {
         IterMut { next: self.head.as_mut().map(|node| &mut **node) }
     }
 }
 
-impl<'a, T> Iterator for IterMut<'a, T> {
+impl<'a, T, A: NodeAllocator> Iterator for IterMut<'a, T, A> {
     type Item = &'a mut T;
     fn next(&mut self) -> Option<Self::Item> {
         // `map`は実行された`Option`値全体をムーブする。
@@ -124,9 +180,173 @@ impl<'a, T> Iterator for IterMut<'a, T> {
     }
 }
 
+// `&self`で読むだけなので、`T`と`A`が`Sync`なら複数のスレッドから同時に辿れる。
+// 分け方と集め方は`par`を参照。
+impl<T: Sync, A: NodeAllocator + Sync> List<T, A> {
+    pub fn par_for_each<F: Fn(&T) + Sync>(&self, f: F) {
+        self.par_map_reduce(f, |(), ()| ());
+    }
+
+    // 各要素を`map`した結果を`reduce`で1つにまとめる。空なら`None`。
+    // `reduce`は結合則を満たす必要がある。結果は実行ごとに変わらない。
+    pub fn par_map_reduce<R, M, F>(&self, map: M, reduce: F) -> Option<R>
+        where R: Send, M: Fn(&T) -> R + Sync, F: Fn(R, R) -> R + Sync
+    {
+        let starts = par::chunk_starts(self.iter(), par::CHUNK_LEN);
+        par::map_reduce(starts, par::CHUNK_LEN, map, reduce)
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+impl<T: Debug, A: NodeAllocator> List<T, A> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", node_ptr(&self.head), "");
+        let mut link = &self.head;
+        while let Some(ref node) = *link {
+            dot.node(&**node as *const Node<T, A>, &format!("{:?}", node.elem));
+            dot.edge(&**node as *const Node<T, A>, node_ptr(&node.next), "next");
+            link = &node.next;
+        }
+        dot.finish()
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// 各ノードは手前のノードの`next`だけが所有しているので、型の上では循環も共有も起こらない。
+// ただ`NodeBox`の中身は raw pointer なので、`head`から辿ったノードに重複がない事は確かめておく。
+// `validate`フィーチャーを有効にすると、push、pop、ソート、マージのたびに呼ばれる。
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut link = &self.head;
+        while let Some(ref node) = *link {
+            let addr: *const Node<T, A> = &**node;
+            assert!(seen.insert(addr), "cycle: {:p} is reachable twice from `head`", addr);
+            link = &node.next;
+        }
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+fn node_ptr<T, A: NodeAllocator>(link: &Link<T, A>) -> *const Node<T, A> {
+    link.as_ref().map_or(ptr::null(), |node| &**node)
+}
+
+// ソート
+
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn sort(&mut self) where T: Ord {
+        self.sort_by(|a, b| a.cmp(b));
+    }
+
+    pub fn sort_by_key<K: Ord, F: FnMut(&T) -> K>(&mut self, mut f: F) {
+        self.sort_by(|a, b| f(a).cmp(&f(b)));
+    }
+
+    // `compare`が panic した場合、要素はすべて drop されてリストは空になる。
+    pub fn sort_by<F: FnMut(&T, &T) -> Ordering>(&mut self, mut compare: F) {
+        let head = self.head.take();
+        self.head = merge_sort(head, &mut compare);
+        self.validate();
+    }
+
+    // 自身と`other`がどちらもソート済みである事を前提に、`other`の要素を取り込む。
+    pub fn merge(&mut self, other: List<T, A>) where T: Ord {
+        self.merge_by(other, |a, b| a.cmp(b));
+    }
+
+    pub fn merge_by<F: FnMut(&T, &T) -> Ordering>(&mut self, mut other: List<T, A>, mut compare: F) {
+        let head = self.head.take();
+        let other_head = other.head.take();
+        self.head = merge(head, other_head, &mut compare);
+        self.validate();
+    }
+}
+
+// 連結リストのソートはノードを繋ぎ変えるだけで済むので、要素の移動も追加の確保も要らない。
+// 再帰で分割するとリストが長いときに stack overflow するので、
+// 長さ 2^i のソート済みの連を`bins[i]`に貯めていくボトムアップのマージソートにする。
+// `bins`は高々64個なので、必要なメモリはリストの長さによらず一定。
+fn merge_sort<T, A, F>(list: Link<T, A>, compare: &mut F) -> Link<T, A>
+    where A: NodeAllocator, F: FnMut(&T, &T) -> Ordering
+{
+    let mut list = Run(list);
+    let mut bins: [Run<T, A>; 64] = array::from_fn(|_| Run(None));
+    while let Some(mut node) = list.0.take() {
+        list.0 = node.next.take();
+        let mut run = Run(Some(node));
+        let mut i = 0;
+        // 添字が大きい`bins`ほど元のリストで前にあった要素を持つ。
+        // 安定ソートにするため、常に前にあった側を`merge`の第1引数にする。
+        while let Some(bin) = bins[i].0.take() {
+            run = Run(merge(Some(bin), run.0.take(), compare));
+            i += 1;
+        }
+        bins[i] = run;
+    }
+
+    let mut sorted = Run(None);
+    for bin in bins.iter_mut() {
+        if bin.0.is_some() {
+            sorted = Run(merge(bin.0.take(), sorted.0.take(), compare));
+        }
+    }
+    sorted.0.take()
+}
+
+// ソート済みの`a`と`b`を1つにまとめる。等しい要素は`a`のものが先になる。
+fn merge<T, A, F>(a: Link<T, A>, b: Link<T, A>, compare: &mut F) -> Link<T, A>
+    where A: NodeAllocator, F: FnMut(&T, &T) -> Ordering
+{
+    let (mut a, mut b) = (Run(a), Run(b));
+    let mut head = Run(None);
+    {
+        let mut tail = &mut head.0;
+        while let (Some(node_a), Some(node_b)) = (a.0.as_ref(), b.0.as_ref()) {
+            let src = if compare(&node_b.elem, &node_a.elem) == Ordering::Less {
+                &mut b.0
+            } else {
+                &mut a.0
+            };
+            let mut node = src.take().unwrap();
+            *src = node.next.take();
+            tail = &mut tail.insert(node).next;
+        }
+        *tail = if a.0.is_some() { a.0.take() } else { b.0.take() };
+    }
+    head.0.take()
+}
+
+// ソートの途中の連。`compare`が panic すると連はそのまま drop されるが、
+// `Link`の drop は`next`を辿って再帰するので、長い連だと stack overflow する。
+// そこで`List::drop`と同じように、ノードを1つずつ外してから drop する。
+struct Run<T, A: NodeAllocator>(Link<T, A>);
+
+impl<T, A: NodeAllocator> Drop for Run<T, A> {
+    fn drop(&mut self) {
+        let mut link = self.0.take();
+        while let Some(mut node) = link {
+            link = node.next.take();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::cell::Cell;
+    use std::mem;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::ptr;
     use super::List;
+    use node_alloc::AllocError;
+    use node_alloc::test::{CountingAlloc, PanicOnDrop};
 
     #[test]
     fn basics() {
@@ -195,5 +415,217 @@ mod test {
         }
         assert_eq!(list.peek(), Some(&2));
     }
-}
 
+    #[test]
+    fn custom_allocator() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut list = List::new_in(&alloc);
+            list.push(1); list.push(2); list.push(3);
+            assert_eq!(alloc.live(), 3);
+
+            assert_eq!(list.pop(), Some(3));
+            assert_eq!(alloc.live(), 2);
+            assert_eq!(list.iter().collect::<Vec<_>>(), vec![&2, &1]);
+        }
+        assert_eq!(alloc.allocs.get(), 3);
+        assert_eq!(alloc.live(), 0);
+    }
+
+    #[test]
+    fn try_push() {
+        let alloc = CountingAlloc::default();
+        alloc.limit.set(Some(2));
+        let mut list = List::new_in(&alloc);
+        assert!(list.try_push(1).is_ok());
+        assert!(list.try_push(2).is_ok());
+
+        match list.try_push(3) {
+            Err((AllocError, elem)) => assert_eq!(elem, 3),
+            Ok(()) => panic!("allocation should fail"),
+        }
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&2, &1]);
+        assert_eq!(alloc.live(), 2);
+
+        alloc.limit.set(None);
+        assert!(list.try_push(3).is_ok());
+        assert_eq!(list.pop(), Some(3));
+    }
+
+    #[test]
+    fn sort() {
+        let mut list = List::new();
+        list.sort();
+        assert_eq!(list.peek(), None);
+
+        for &v in &[3, 1, 4, 1, 5, 9, 2, 6, 5, 3] {
+            list.push(v);
+        }
+        list.sort();
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![1, 1, 2, 3, 3, 4, 5, 5, 6, 9]);
+
+        list.sort_by(|a, b| b.cmp(a));
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![9, 6, 5, 5, 4, 3, 3, 2, 1, 1]);
+    }
+
+    #[test]
+    fn sort_is_stable() {
+        let mut list = List::new();
+        for &v in &[(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (0, 'e')] {
+            list.push(v);
+        }
+        // push したので先頭から (0, 'e'), (1, 'd'), (2, 'c'), (1, 'b'), (2, 'a') の順。
+        list.sort_by_key(|&(k, _)| k);
+        assert_eq!(
+            list.iter().map(|&(_, c)| c).collect::<String>(),
+            "edbca"
+        );
+    }
+
+    // push のたびにリスト全体を確かめると終わらないので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn sort_long_list() {
+        let mut list = List::new();
+        let mut x: u32 = 1;
+        for _ in 0..1_000_000 {
+            x ^= x << 13; x ^= x >> 17; x ^= x << 5;
+            list.push(x);
+        }
+        list.sort();
+        let mut iter = list.iter();
+        let mut prev = iter.next().unwrap();
+        let mut count = 1;
+        for v in iter {
+            assert!(prev <= v);
+            prev = v;
+            count += 1;
+        }
+        assert_eq!(count, 1_000_000);
+    }
+
+    // 長い連を持っている途中で`compare`が panic しても、stack overflow せずにすべて drop される。
+    // push のたびにリスト全体を確かめると終わらないので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn sort_panic_long_list() {
+        let drops = Cell::new(0);
+        let mut list = List::new();
+        let mut x: u32 = 1;
+        for _ in 0..1_000_000 {
+            x ^= x << 13; x ^= x >> 17; x ^= x << 5;
+            list.push((x, PanicOnDrop(&drops, false)));
+        }
+        let mut calls = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            list.sort_by(|a, b| {
+                calls += 1;
+                if calls == 15_000_000 {
+                    panic!("compare panicked");
+                }
+                a.0.cmp(&b.0)
+            });
+        }));
+        assert!(result.is_err());
+        assert_eq!(list.peek().map(|e| e.0), None);
+        assert_eq!(drops.get(), 1_000_000);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = List::new();
+        for &v in &[(7, 'a'), (3, 'a'), (1, 'a')] {
+            a.push(v);
+        }
+        let mut b = List::new();
+        for &v in &[(8, 'b'), (3, 'b'), (2, 'b'), (1, 'b')] {
+            b.push(v);
+        }
+        a.merge_by(b, |x, y| x.0.cmp(&y.0));
+        assert_eq!(
+            a.iter().cloned().collect::<Vec<_>>(),
+            vec![(1, 'a'), (1, 'b'), (2, 'b'), (3, 'a'), (3, 'b'), (7, 'a'), (8, 'b')]
+        );
+
+        let mut c = List::new();
+        c.merge(List::new());
+        assert_eq!(c.peek(), None);
+        let mut d = List::new();
+        d.push(2); d.push(1);
+        c.merge(d);
+        assert_eq!(c.iter().cloned().collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn drop_panic_safety() {
+        let drops: Vec<_> = (0..5).map(|_| Cell::new(0)).collect();
+        let mut list = List::new();
+        for (i, d) in drops.iter().enumerate() {
+            list.push(PanicOnDrop(d, i == 2));
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(move || drop(list)));
+        assert!(result.is_err());
+        // panic した要素のあとも drop は続き、どの要素も1回だけ drop される。
+        assert!(drops.iter().all(|d| d.get() == 1));
+    }
+
+    // 10 万回の push でそれぞれリスト全体を辿る事になるので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn par_map_reduce() {
+        let mut list = List::new();
+        assert_eq!(list.par_map_reduce(|&x| x, |a, b| a + b), None);
+        list.push(1u64);
+        assert_eq!(list.par_map_reduce(|&x| x * 2, |a, b| a + b), Some(2));
+        for i in 2..=100_000 {
+            list.push(i);
+        }
+        assert_eq!(list.par_map_reduce(|&x| x, |a, b| a + b), Some(100_000 * 100_001 / 2));
+        let max = list.par_map_reduce(|&x| x, |a, b| a.max(b));
+        assert_eq!(max, Some(100_000));
+
+        // 要素の並び順 (先頭から) を保ったまま集められる。
+        let collected = list.par_map_reduce(|&x| vec![x], |mut a, b| {
+            a.extend(b);
+            a
+        });
+        assert_eq!(collected, Some(list.iter().cloned().collect::<Vec<_>>()));
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list = List::new();
+        list.debug_assert_invariants();
+        list.push(1);
+        list.push(2);
+        list.debug_assert_invariants();
+
+        // 末尾のノードの`next`が先頭を指している (循環している) 状態。
+        // 先頭の`NodeBox`を複製して繋ぐので、確かめたら drop せずに外す。
+        unsafe {
+            let head = ptr::read(list.head.as_ref().unwrap());
+            let last = &mut list.head.as_mut().unwrap().next.as_mut().unwrap().next as *mut _;
+            ptr::write(last, Some(head));
+            let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+            assert!(result.is_err());
+            mem::forget((*last).take());
+        }
+
+        assert_eq!(list.pop(), Some(2));
+        assert_eq!(list.pop(), Some(1));
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut list = List::new();
+        list.push("a");
+        // 要素の`Debug`出力に含まれる`"`や`\`もエスケープされる。
+        list.push("b\"\\c");
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert!(out.contains("\\n\\\"a\\\"\"]"));
+        assert!(out.contains("\\n\\\"b\\\\\\\"\\\\\\\\c\\\"\"]"));
+    }
+}
@@ -0,0 +1,66 @@
+// `persistent_stack`と`ok_stack`の並列処理の共通部分。
+// 連結リストはランダムアクセスできないので、まず先頭から一度だけ辿って
+// `CHUNK_LEN`要素ごとの開始位置 (そこから始まるイテレータ) を集めておく。
+// 各スレッドは次に処理するチャンクの番号を`AtomicUsize`から取っていくので、
+// 処理の重さに偏りがあっても空いたスレッドが残りを拾える。
+
+// チャンクの区切りはスレッド数によらず固定で、チャンクごとの結果は最後に
+// 先頭から順に`reduce`する。そのため浮動小数点の和のように結合則が厳密には
+// 成り立たない場合でも、何度実行しても同じ結果になる。
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+pub(crate) const CHUNK_LEN: usize = 1024;
+
+// `iter`を`chunk_len`要素ごとに区切り、各チャンクの開始位置を返す。
+pub(crate) fn chunk_starts<I: Iterator + Clone>(mut iter: I, chunk_len: usize) -> Vec<I> {
+    let mut starts = Vec::new();
+    while iter.clone().next().is_some() {
+        starts.push(iter.clone());
+        iter.nth(chunk_len - 1);
+    }
+    starts
+}
+
+// 各チャンクを`map`してから`reduce`で畳み込み、チャンクの結果をさらに順に畳み込む。
+// 要素がなければ`None`。`map`や`reduce`が panic した場合は、全スレッドの終了を待ってから
+// 呼び出し元で panic する (`thread::scope`と同じ)。
+pub(crate) fn map_reduce<I, R, M, F>(starts: Vec<I>, chunk_len: usize, map: M, reduce: F) -> Option<R>
+    where I: Iterator + Clone + Sync,
+          R: Send,
+          M: Fn(I::Item) -> R + Sync,
+          F: Fn(R, R) -> R + Sync
+{
+    let run = |start: &I| start.clone().take(chunk_len).map(&map).reduce(&reduce);
+
+    // チャンクが1つならスレッドを作るまでもない。
+    if starts.len() <= 1 {
+        return starts.first().and_then(run);
+    }
+
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(starts.len());
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<R>>> = starts.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let start = match starts.get(i) {
+                        Some(start) => start,
+                        None => break,
+                    };
+                    *results[i].lock().unwrap() = run(start);
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .filter_map(|result| result.into_inner().unwrap())
+        .reduce(&reduce)
+}
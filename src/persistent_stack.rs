@@ -1,6 +1,11 @@
 // third.rs
 
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ptr;
 use std::sync::Arc;
+use dot::Dot;
+use par;
 
 pub struct List<T> {
     head: Link<T>,
@@ -18,6 +23,8 @@ impl<T> List<T> {
         List { head: None }
     }
 
+    // `Arc::new`は確保に失敗すると abort し、失敗を返す`try_new`は stable にない。
+    // 失敗しても`elem`を確実に返せる方法がないので、`try_append`は用意していない。
     pub fn append(&self, elem: T) -> List<T> {
         // Copy trait を実装している型の値を別の変数に代入した場合、
         // 元の変数からCopyされるため、元の変数にも引き続きアクセスできる。
@@ -38,6 +45,72 @@ impl<T> List<T> {
     pub fn head(&self) -> Option<&T> {
         self.head.as_ref().map(|node| &node.elem)
     }
+
+    // 2つのリストが同じノードを先頭として共有しているか。要素は比較しない。
+    pub fn ptr_eq(&self, other: &List<T>) -> bool {
+        match (&self.head, &other.head) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+// その場で変更する操作。他のバージョンからは変更が見えないように、
+// 共有されているノードは書き込む前に複製する (copy-on-write)。
+// 誰とも共有していなければ (`Arc`の strong count が 1 なら) 複製せずにそのまま書き換えるので、
+// 1つのバージョンだけを使い続ける間は`append`/`tail`と違ってノードを作り直さずに済む。
+impl<T> List<T> {
+    // `append`と違い`self`の先頭を付け替えるだけなので、古い先頭の参照を増やしたり減らしたりしない。
+    pub fn push_mut(&mut self, elem: T) {
+        let next = self.head.take();
+        self.head = Some(Arc::new(Node { elem, next }));
+        self.validate();
+    }
+}
+
+impl<T: Clone> List<T> {
+    // 先頭のノードを共有していなければ、要素をそのまま取り出してノードを解放する。
+    // 共有していれば、要素を複製して次のノードへ進むだけで、ノードは他のバージョンに残す。
+    pub fn pop_mut(&mut self) -> Option<T> {
+        let elem = self.head.take().map(|node| match Arc::try_unwrap(node) {
+            Ok(mut node) => {
+                self.head = node.next.take();
+                node.elem
+            }
+            Err(node) => {
+                self.head = node.next.clone();
+                node.elem.clone()
+            }
+        });
+        self.validate();
+        elem
+    }
+
+    // 先頭のノードを共有していれば、`Arc::make_mut`がそのノードだけを複製して`self`の先頭にする。
+    // 複製したノードも後ろのノードを指したままなので、複製されるのは1つだけ。
+    pub fn head_mut(&mut self) -> Option<&mut T> {
+        if let Some(ref mut node) = self.head {
+            Arc::make_mut(node);
+        }
+        self.validate();
+        // `make_mut`のあとなので先頭は`self`だけのもので、`get_mut`は必ず成功する。
+        self.head.as_mut().map(|node| &mut Arc::get_mut(node).unwrap().elem)
+    }
+}
+
+// `Arc::make_mut`で使う。後ろのノードは`Arc`を複製して共有する。
+impl<T: Clone> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        Node { elem: self.elem.clone(), next: self.next.clone() }
+    }
+}
+
+// 先頭の`Arc`を複製するだけなので O(1) で、ノードはすべて共有される。
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        List { head: self.head.clone() }
+    }
 }
 
 pub struct Iter<'a, T: 'a> {
@@ -50,6 +123,13 @@ impl<T> List<T> {
     }
 }
 
+// ノードへの参照を持っているだけなので、`T: Clone`でなくても複製できる。
+impl<'a, T> Clone for Iter<'a, T> {
+    fn clone(&self) -> Self {
+        Iter { next: self.next }
+    }
+}
+
 impl<'a, T> Iterator for Iter<'a, T> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
@@ -60,7 +140,92 @@ impl<'a, T> Iterator for Iter<'a, T> {
     }
 }
 
+// ノードは`Arc`で共有されていて変更されないので、複数のスレッドから同時に読める。
+// ただし`&Arc<T>`をスレッド間で共有するには、`T: Sync`に加えて`T: Send`も要る。
+// 分け方と集め方は`par`を参照。
+impl<T: Send + Sync> List<T> {
+    pub fn par_for_each<F: Fn(&T) + Sync>(&self, f: F) {
+        self.par_map_reduce(f, |(), ()| ());
+    }
+
+    // 各要素を`map`した結果を`reduce`で1つにまとめる。空なら`None`。
+    // `reduce`は結合則を満たす必要がある。結果は実行ごとに変わらない。
+    pub fn par_map_reduce<R, M, F>(&self, map: M, reduce: F) -> Option<R>
+        where R: Send, M: Fn(&T) -> R + Sync, F: Fn(R, R) -> R + Sync
+    {
+        let starts = par::chunk_starts(self.iter(), par::CHUNK_LEN);
+        par::map_reduce(starts, par::CHUNK_LEN, map, reduce)
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// ノードは他のバージョンと共有されうるので、strong count の正確な値はこのリストだけからは決まらない。
+// 決まっているのは weak count で、`Weak`はどこでも作らないので常に 0 のはず
+// (`Weak`があると`Drop`の`try_unwrap`や`make_mut`の判断が変わってしまう)。あわせて循環していない事も確かめる。
+// `head_mut`のあとに先頭の strong count が 1 になっている事は、`head_mut`自身の`get_mut`で確かめている。
+// `validate`フィーチャーを有効にすると、`push_mut`、`pop_mut`、`head_mut`のたびに呼ばれる。
+impl<T> List<T> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut link = &self.head;
+        while let Some(ref node) = *link {
+            let addr = Arc::as_ptr(node);
+            assert!(seen.insert(addr), "cycle: {:p} is reachable twice from `head`", addr);
+            assert_eq!(Arc::weak_count(node), 0, "unexpected weak reference to {:p}", addr);
+            link = &node.next;
+        }
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
 // 状態変更をしない実装のため、`third::List`の`Iter`や`IterMut`は実装できない。
+// (`head_mut`のように先頭だけなら copy-on-write で変更できるが、`IterMut`のために
+// 辿ったノードをすべて複製するのでは永続リストを使う意味がない。)
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// 各ノードには`Arc`の strong count も書く。2 以上なら他のリストやノードからも指されている。
+impl<T: Debug> List<T> {
+    pub fn to_dot(&self) -> String {
+        write_dot(&[("head".to_string(), self)])
+    }
+}
+
+// 複数のバージョンを1つの図にまとめる。根の名前は`versions`の順に`v0`、`v1`...となる。
+// 既に書いたノードに辿り着いたらそこで止めるので、共有されている後ろの部分は一度だけ現れる。
+pub fn versions_to_dot<T: Debug>(versions: &[&List<T>]) -> String {
+    let roots: Vec<_> = versions.iter().enumerate().map(|(i, &list)| (format!("v{}", i), list)).collect();
+    write_dot(&roots)
+}
+
+// `zipper`からも使う。
+pub(crate) fn write_dot<T: Debug>(roots: &[(String, &List<T>)]) -> String {
+    let mut dot = Dot::new();
+    let mut seen = HashSet::new();
+    for &(ref name, list) in roots {
+        dot.root(name, node_ptr(&list.head), "");
+        let mut link = &list.head;
+        while let Some(ref node) = *link {
+            if !seen.insert(Arc::as_ptr(node)) {
+                break;
+            }
+            let label = format!("{:?}\nstrong={}", node.elem, Arc::strong_count(node));
+            dot.node(Arc::as_ptr(node), &label);
+            dot.edge(Arc::as_ptr(node), node_ptr(&node.next), "next");
+            link = &node.next;
+        }
+    }
+    dot.finish()
+}
+
+fn node_ptr<T>(link: &Link<T>) -> *const Node<T> {
+    link.as_ref().map_or(ptr::null(), Arc::as_ptr)
+}
 
 impl<T> Drop for List<T> {
     fn drop(&mut self) {
@@ -79,7 +244,8 @@ impl<T> Drop for List<T> {
 
 #[cfg(test)]
 mod test {
-    use super::List;
+    use std::panic::{self, AssertUnwindSafe};
+    use super::{versions_to_dot, List};
 
     #[test]
     fn basics() {
@@ -103,4 +269,157 @@ mod test {
         assert_eq!(iter.next(), Some(&1));
         assert_eq!(list.head(), Some(&2));
     }
+
+    #[test]
+    fn mut_ops() {
+        let mut list = List::new();
+        assert_eq!(list.pop_mut(), None);
+        assert_eq!(list.head_mut(), None);
+
+        list.push_mut(1);
+        list.push_mut(2);
+        *list.head_mut().unwrap() *= 10;
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&20, &1]);
+        assert_eq!(list.pop_mut(), Some(20));
+        assert_eq!(list.pop_mut(), Some(1));
+        assert_eq!(list.pop_mut(), None);
+    }
+
+    #[test]
+    fn mut_ops_copy_on_write() {
+        use std::sync::Arc;
+
+        let mut list = List::new().append(1).append(2);
+        let snapshot = list.clone();
+
+        // 共有されている先頭だけが複製され、`1`のノードは共有されたまま。
+        *list.head_mut().unwrap() = 20;
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&20, &1]);
+        assert_eq!(snapshot.iter().collect::<Vec<_>>(), vec![&2, &1]);
+        assert!(list.tail().ptr_eq(&snapshot.tail()));
+
+        // 複製した先頭はもう誰とも共有していないので、その場で書き換わる。
+        let head = Arc::as_ptr(list.head.as_ref().unwrap());
+        *list.head_mut().unwrap() += 1;
+        assert_eq!(Arc::as_ptr(list.head.as_ref().unwrap()), head);
+        list.push_mut(3);
+        assert_eq!(list.pop_mut(), Some(3));
+        assert_eq!(list.pop_mut(), Some(21));
+
+        // `1`のノードは`snapshot`と共有しているので、取り出しても`snapshot`からは消えない。
+        assert_eq!(list.pop_mut(), Some(1));
+        assert_eq!(list.head(), None);
+        assert_eq!(snapshot.iter().collect::<Vec<_>>(), vec![&2, &1]);
+    }
+
+    #[test]
+    fn mut_ops_clone_only_when_shared() {
+        use std::cell::Cell;
+
+        // 複製された回数を数える。
+        struct Counted<'a>(&'a Cell<usize>);
+        impl<'a> Clone for Counted<'a> {
+            fn clone(&self) -> Self {
+                self.0.set(self.0.get() + 1);
+                Counted(self.0)
+            }
+        }
+
+        let clones = Cell::new(0);
+        let mut list = List::new();
+        for _ in 0..100 {
+            list.push_mut(Counted(&clones));
+            list.head_mut().unwrap();
+        }
+        while list.pop_mut().is_some() {}
+        assert_eq!(clones.get(), 0);
+
+        list.push_mut(Counted(&clones));
+        list.push_mut(Counted(&clones));
+        let snapshot = list.clone();
+        list.head_mut().unwrap();
+        assert_eq!(clones.get(), 1);
+        // 複製した先頭は`list`だけのものなので、取り出しても複製しない。次の`1`は共有している。
+        list.pop_mut().unwrap();
+        assert_eq!(clones.get(), 1);
+        list.pop_mut().unwrap();
+        assert_eq!(clones.get(), 2);
+        drop(snapshot);
+    }
+
+    #[test]
+    fn par_map_reduce() {
+        let mut list = List::new();
+        assert_eq!(list.par_map_reduce(|&x| x, |a, b| a + b), None);
+        for i in 0..100_000u64 {
+            list = list.append(i);
+        }
+        assert_eq!(list.par_map_reduce(|&x| x, |a, b| a + b), Some(100_000 * 99_999 / 2));
+        // 先頭から順に畳み込むので、結合則だけを満たす (交換則を満たさない) 演算でもよい。
+        let first_and_last = list.par_map_reduce(|&x| (x, x), |a, b| (a.0, b.1));
+        assert_eq!(first_and_last, Some((99_999, 0)));
+
+        // 浮動小数点の和は足す順番で結果が変わるが、チャンクの区切りと畳み込む順番は
+        // 固定なので、何度実行しても同じ値になる。
+        let mut floats = List::new();
+        for i in 0..50_000 {
+            floats = floats.append(1.0 / (i as f64 + 1.0) * if i % 2 == 0 { 1e10 } else { 1e-10 });
+        }
+        let sum = floats.par_map_reduce(|&x| x, |a, b| a + b).unwrap();
+        for _ in 0..10 {
+            assert_eq!(floats.par_map_reduce(|&x| x, |a, b| a + b).unwrap().to_bits(), sum.to_bits());
+        }
+    }
+
+    #[test]
+    fn par_for_each() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let list = (1..=5000).fold(List::new(), |list, i| list.append(i));
+        let sum = AtomicUsize::new(0);
+        list.par_for_each(|&x| {
+            sum.fetch_add(x, Ordering::Relaxed);
+        });
+        assert_eq!(sum.into_inner(), 5000 * 5001 / 2);
+    }
+
+    #[test]
+    fn to_dot() {
+        let base = List::new().append(1).append(2);
+        let a = base.append(3);
+        let b = base.append(4);
+
+        let out = base.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert_eq!(out.matches("strong=").count(), 2);
+
+        // 共有されている`2`と`1`は一度だけ現れ、どちらのバージョンからも辿れる。
+        let out = versions_to_dot(&[&a, &b, &base]);
+        assert_eq!(out.matches("strong=").count(), 4);
+        assert_eq!(out.matches("\\n2\\nstrong=3\"").count(), 1);
+        assert_eq!(out.matches("\\n1\\nstrong=1\"").count(), 1);
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 3);
+        assert!(out.contains("\"v2\" -> "));
+    }
+
+    #[test]
+    fn invariants() {
+        use std::sync::Arc;
+
+        let mut list = List::new().append(1).append(2);
+        let shared = list.clone();
+        list.debug_assert_invariants();
+        list.push_mut(3);
+        list.debug_assert_invariants();
+
+        // 2つ目のノードに`Weak`が作られた状態。
+        let weak = Arc::downgrade(list.head.as_ref().unwrap().next.as_ref().unwrap());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        drop(weak);
+
+        assert_eq!(list.pop_mut(), Some(3));
+        assert_eq!(list.pop_mut(), Some(2));
+        list.debug_assert_invariants();
+        shared.debug_assert_invariants();
+    }
 }
@@ -0,0 +1,208 @@
+// 優先度ごとに`unsafe_deque::List`を1本ずつ持つ優先度付きキュー。
+// 優先度は`0..levels`の整数で、大きいものほど先に取り出される。
+// 同じ優先度の中では入れた順 (FIFO) に取り出されるので、ヒープと違って順序が安定している。
+
+// `push`は該当するリストの末尾に繋ぐだけなので O(1)。
+// `pop`は空でない一番高い優先度を探すので O(levels)。ただし`top`より上は空だとわかっているので、
+// そこから下へ探すだけでよい。優先度の数が少ない (数個〜数十個) 使い方を想定している。
+
+use std::fmt::Debug;
+use std::iter::Rev;
+use std::slice;
+use dot::Dot;
+use unsafe_deque::{self, List};
+
+pub struct PriorityQueue<T> {
+    // 添字が優先度。
+    levels: Vec<List<T>>,
+    // `top`より高い優先度のリストはすべて空。空のキューでは 0。
+    top: usize,
+    // `unsafe_deque::List`は長さを持たないので、ここで数える。
+    len: usize,
+}
+
+// 取り出される順に要素を辿る。
+pub struct Iter<'a, T: 'a> {
+    levels: Rev<slice::Iter<'a, List<T>>>,
+    cur: Option<unsafe_deque::Iter<'a, T>>,
+}
+
+impl<T> PriorityQueue<T> {
+    // `levels`が 0 の場合は panic する。
+    pub fn new(levels: usize) -> Self {
+        assert!(levels > 0, "at least one priority level is required");
+        PriorityQueue { levels: (0..levels).map(|_| List::new()).collect(), top: 0, len: 0 }
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // `priority`が`levels()`以上の場合は panic する。
+    pub fn push(&mut self, priority: usize, elem: T) {
+        assert!(priority < self.levels.len(), "priority {} is out of range 0..{}", priority, self.levels.len());
+        self.levels[priority].push(elem);
+        self.len += 1;
+        if priority > self.top {
+            self.top = priority;
+        }
+    }
+
+    // 一番高い優先度の、一番先に入れた要素を取り出す。
+    pub fn pop(&mut self) -> Option<(usize, T)> {
+        if self.len == 0 {
+            return None;
+        }
+        let priority = self.highest();
+        let elem = self.levels[priority].pop().expect("`len` does not match the lists");
+        self.len -= 1;
+        // 次に探すときは、ここから下を見ればよい。
+        self.top = priority;
+        Some((priority, elem))
+    }
+
+    // 次に`pop`される要素。
+    pub fn peek(&self) -> Option<(usize, &T)> {
+        (0..=self.top).rev().find_map(|p| self.levels[p].front().map(|elem| (p, elem)))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { levels: self.levels.iter().rev(), cur: None }
+    }
+
+    pub fn clear(&mut self) {
+        for list in &mut self.levels {
+            while list.pop().is_some() {}
+        }
+        self.top = 0;
+        self.len = 0;
+    }
+
+    // 空でない一番高い優先度。キューが空でない時だけ呼ぶ。
+    fn highest(&self) -> usize {
+        (0..=self.top).rev().find(|&p| self.levels[p].front().is_some()).expect("`len` does not match the lists")
+    }
+}
+
+// 優先度ごとのリストを、`head[p]`と`tail[p]`を根にして1つの図に並べる (デバッグ用)。
+// 空の優先度も根だけは書くので、どの優先度が空なのかもわかる。
+impl<T: Debug> PriorityQueue<T> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        for (p, list) in self.levels.iter().enumerate() {
+            list.write_dot(&mut dot, &format!("head[{}]", p), &format!("tail[{}]", p));
+        }
+        dot.finish()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(elem) = self.cur.as_mut().and_then(|it| it.next()) {
+                return Some(elem);
+            }
+            self.cur = Some(self.levels.next()?.iter());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PriorityQueue;
+
+    #[test]
+    fn basics() {
+        let mut queue = PriorityQueue::new(3);
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.peek(), None);
+
+        queue.push(0, "low");
+        queue.push(2, "high");
+        queue.push(1, "mid");
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.peek(), Some((2, &"high")));
+        assert_eq!(queue.pop(), Some((2, "high")));
+        assert_eq!(queue.pop(), Some((1, "mid")));
+
+        // 低い優先度を取り出している途中でも、高い優先度が入ればそちらが先になる。
+        queue.push(2, "urgent");
+        assert_eq!(queue.pop(), Some((2, "urgent")));
+        assert_eq!(queue.pop(), Some((0, "low")));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn fifo_within_priority() {
+        let mut queue = PriorityQueue::new(2);
+        for i in 0..6 {
+            queue.push(i % 2, i);
+        }
+        let popped: Vec<_> = (0..6).map(|_| queue.pop().unwrap()).collect();
+        assert_eq!(popped, vec![(1, 1), (1, 3), (1, 5), (0, 0), (0, 2), (0, 4)]);
+    }
+
+    #[test]
+    fn iter() {
+        let mut queue = PriorityQueue::new(4);
+        queue.push(1, 'a');
+        queue.push(3, 'b');
+        queue.push(1, 'c');
+        queue.push(3, 'd');
+        // 優先度 0 と 2 は空のまま。
+        assert_eq!(queue.iter().cloned().collect::<String>(), "bdac");
+
+        let mut order = String::new();
+        while let Some((_, c)) = queue.pop() {
+            order.push(c);
+        }
+        assert_eq!(order, "bdac");
+        assert_eq!(queue.iter().next(), None);
+    }
+
+    #[test]
+    fn clear() {
+        let mut queue = PriorityQueue::new(2);
+        queue.push(1, 1);
+        queue.push(0, 0);
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+        queue.push(0, 2);
+        assert_eq!(queue.pop(), Some((0, 2)));
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut queue = PriorityQueue::new(3);
+        queue.push(0, "low");
+        queue.push(2, "high");
+        queue.push(2, "urgent");
+        let out = queue.to_dot();
+        assert!(out.contains("\"head[0]\" -> "));
+        assert!(out.contains("\"head[2]\" -> "));
+        assert!(out.contains("\"tail[2]\" -> "));
+        // 空の優先度は根だけで、矢印はない。
+        assert!(out.contains("\"head[1]\" [shape=plaintext];"));
+        assert!(!out.contains("\"head[1]\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert!(out.contains("\\n\\\"urgent\\\"\"]"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn priority_out_of_range() {
+        let mut queue = PriorityQueue::new(2);
+        queue.push(2, ());
+    }
+}
@@ -0,0 +1,427 @@
+// skew binary random-access list (Okasaki)。
+// `persistent_stack::List`と同じく`Arc`で構造を共有する永続リストだが、
+// 添字によるアクセスと更新が O(log n) でできる。`append`、`head`、`tail`は O(1) のまま。
+
+// 要素は完全二分木の列として持つ。木の大きさは 2^k - 1 で、列の先頭の2つだけが
+// 同じ大きさになりうる (skew binary 表現)。
+// `append`では先頭の2つの木が同じ大きさなら新しい要素を根にしてまとめ、
+// そうでなければ大きさ 1 の木を先頭に足すだけなので、繰り上がりが連鎖しない。
+// 木の数も高さも O(log n) なので、`get`は列を辿ってから木を降りれば O(log n) で済む。
+
+use std::fmt::Debug;
+use std::ptr;
+use std::sync::Arc;
+use dot::Dot;
+
+pub struct List<T> {
+    head: Link<T>,
+    len: usize,
+}
+
+type Link<T> = Option<Arc<Digit<T>>>;
+
+// 木の列を繋ぐ連結リストの各ノード。
+struct Digit<T> {
+    size: usize,
+    tree: Arc<Tree<T>>,
+    next: Link<T>,
+}
+
+// 要素は前順 (根、左、右) に並んでいる。
+enum Tree<T> {
+    Leaf(T),
+    Node(T, Arc<Tree<T>>, Arc<Tree<T>>),
+}
+
+impl<T> Tree<T> {
+    fn elem(&self) -> &T {
+        match *self {
+            Tree::Leaf(ref elem) | Tree::Node(ref elem, _, _) => elem,
+        }
+    }
+
+    // `size`はこの木の要素数。
+    fn get(&self, size: usize, i: usize) -> &T {
+        match *self {
+            Tree::Leaf(ref elem) => elem,
+            Tree::Node(ref elem, ref left, ref right) => {
+                let half = size / 2;
+                if i == 0 {
+                    elem
+                } else if i <= half {
+                    left.get(half, i - 1)
+                } else {
+                    right.get(half, i - 1 - half)
+                }
+            }
+        }
+    }
+}
+
+impl<T: Clone> Tree<T> {
+    // 根から`i`番目の要素までの経路だけを作り直し、残りの部分木は共有する。
+    fn update(&self, size: usize, i: usize, value: T) -> Tree<T> {
+        match *self {
+            Tree::Leaf(_) => Tree::Leaf(value),
+            Tree::Node(ref elem, ref left, ref right) => {
+                let half = size / 2;
+                if i == 0 {
+                    Tree::Node(value, left.clone(), right.clone())
+                } else if i <= half {
+                    let left = Arc::new(left.update(half, i - 1, value));
+                    Tree::Node(elem.clone(), left, right.clone())
+                } else {
+                    let right = Arc::new(right.update(half, i - 1 - half, value));
+                    Tree::Node(elem.clone(), left.clone(), right)
+                }
+            }
+        }
+    }
+}
+
+impl<T> List<T> {
+    pub fn new() -> Self {
+        List { head: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&self, elem: T) -> List<T> {
+        let digit = match self.head {
+            Some(ref first) => match first.next {
+                Some(ref second) if first.size == second.size => Digit {
+                    size: first.size * 2 + 1,
+                    tree: Arc::new(Tree::Node(elem, first.tree.clone(), second.tree.clone())),
+                    next: second.next.clone(),
+                },
+                _ => Digit { size: 1, tree: Arc::new(Tree::Leaf(elem)), next: self.head.clone() },
+            },
+            None => Digit { size: 1, tree: Arc::new(Tree::Leaf(elem)), next: None },
+        };
+        let list = List { head: Some(Arc::new(digit)), len: self.len + 1 };
+        list.validate();
+        list
+    }
+
+    // 先頭の木の根を取り除くと、半分の大きさの木が2つ残る。
+    pub fn tail(&self) -> List<T> {
+        let first = match self.head {
+            Some(ref first) => first,
+            None => return List::new(),
+        };
+        let head = match *first.tree {
+            Tree::Leaf(_) => first.next.clone(),
+            Tree::Node(_, ref left, ref right) => {
+                let half = first.size / 2;
+                let right = Digit { size: half, tree: right.clone(), next: first.next.clone() };
+                let left = Digit { size: half, tree: left.clone(), next: Some(Arc::new(right)) };
+                Some(Arc::new(left))
+            }
+        };
+        let list = List { head, len: self.len - 1 };
+        list.validate();
+        list
+    }
+
+    pub fn head(&self) -> Option<&T> {
+        self.head.as_ref().map(|digit| digit.tree.elem())
+    }
+
+    // 先頭 (最後に`append`した要素) が 0 番目。
+    pub fn get(&self, mut i: usize) -> Option<&T> {
+        let mut link = &self.head;
+        while let Some(ref digit) = *link {
+            if i < digit.size {
+                return Some(digit.tree.get(digit.size, i));
+            }
+            i -= digit.size;
+            link = &digit.next;
+        }
+        None
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { digits: self.head.as_deref(), trees: Vec::new(), len: self.len }
+    }
+}
+
+impl<T: Clone> List<T> {
+    // `i`番目の要素を`value`に置き換えた新しいリストを返す。`self`はそのまま使える。
+    // 作り直すのは目的の木までの列と、木の中の根からの経路だけなので O(log n)。
+    pub fn update(&self, i: usize, value: T) -> List<T> {
+        assert!(i < self.len, "index out of bounds: the len is {} but the index is {}", self.len, i);
+        let list = List { head: update(&self.head, i, value), len: self.len };
+        list.validate();
+        list
+    }
+}
+
+fn update<T: Clone>(link: &Link<T>, i: usize, value: T) -> Link<T> {
+    let digit = link.as_ref().expect("index is checked by the caller");
+    let new = if i < digit.size {
+        Digit {
+            size: digit.size,
+            tree: Arc::new(digit.tree.update(digit.size, i, value)),
+            next: digit.next.clone(),
+        }
+    } else {
+        Digit {
+            size: digit.size,
+            tree: digit.tree.clone(),
+            next: update(&digit.next, i - digit.size, value),
+        }
+    };
+    Some(Arc::new(new))
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// - 木の大きさはどれも 2^k - 1 で、列の先頭の2つだけが同じ大きさになりうる。それ以降は狭義単調増加。
+// - 各木の形が大きさと合っている (大きさ 1 なら葉、それ以外は半分ずつの部分木を持つ)。
+// - 大きさの合計が`len`と等しい。
+// 木の中まで全部辿るので O(n)。`validate`フィーチャーを有効にすると、新しいリストを作るたびに呼ばれる。
+impl<T> List<T> {
+    pub fn debug_assert_invariants(&self) {
+        let mut total = 0;
+        let mut sizes = Vec::new();
+        let mut link = &self.head;
+        while let Some(ref digit) = *link {
+            assert!((digit.size + 1).is_power_of_two(), "tree size {} is not 2^k - 1", digit.size);
+            if let Some(&prev) = sizes.last() {
+                let may_be_equal = sizes.len() == 1;
+                assert!(prev < digit.size || (may_be_equal && prev == digit.size), "tree sizes are not in skew binary order: {:?}, {}", sizes, digit.size);
+            }
+            check_tree(&digit.tree, digit.size);
+            sizes.push(digit.size);
+            total += digit.size;
+            link = &digit.next;
+        }
+        assert_eq!(self.len, total, "`len` does not match the total size of the trees");
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+fn check_tree<T>(tree: &Tree<T>, size: usize) {
+    match *tree {
+        Tree::Leaf(_) => assert_eq!(size, 1, "a leaf is given size {}", size),
+        Tree::Node(_, ref left, ref right) => {
+            assert!(size > 1, "a node is given size {}", size);
+            check_tree(left, size / 2);
+            check_tree(right, size / 2);
+        }
+    }
+}
+
+// 構造を Graphviz の DOT 形式で出力する (デバッグ用)。
+// 木の列のノード (`size`) から`next`で次の列のノードへ、`tree`で木の根へ矢印を引く。
+// 列のノードにも木のノードにも`Arc`の strong count を書くので、
+// `update`や`append`の前後のリストでどこが共有されているかがわかる。
+impl<T: Debug> List<T> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", digit_ptr(&self.head), "");
+        let mut link = &self.head;
+        while let Some(ref digit) = *link {
+            let label = format!("size={}\nstrong={}", digit.size, Arc::strong_count(digit));
+            dot.node(Arc::as_ptr(digit), &label);
+            dot.edge(Arc::as_ptr(digit), digit_ptr(&digit.next), "next");
+            dot.edge(Arc::as_ptr(digit), Arc::as_ptr(&digit.tree), "tree");
+            tree_to_dot(&mut dot, &digit.tree);
+            link = &digit.next;
+        }
+        dot.finish()
+    }
+}
+
+fn digit_ptr<T>(link: &Link<T>) -> *const Digit<T> {
+    link.as_ref().map_or(ptr::null(), Arc::as_ptr)
+}
+
+// 木の高さは O(log n) なので再帰で辿ってよい。
+fn tree_to_dot<T: Debug>(dot: &mut Dot, tree: &Arc<Tree<T>>) {
+    let label = format!("{:?}\nstrong={}", tree.elem(), Arc::strong_count(tree));
+    dot.node(Arc::as_ptr(tree), &label);
+    if let Tree::Node(_, ref left, ref right) = **tree {
+        dot.edge(Arc::as_ptr(tree), Arc::as_ptr(left), "left");
+        dot.edge(Arc::as_ptr(tree), Arc::as_ptr(right), "right");
+        tree_to_dot(dot, left);
+        tree_to_dot(dot, right);
+    }
+}
+
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        List { head: self.head.clone(), len: self.len }
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        List::new()
+    }
+}
+
+// 列の長さも木の高さも O(log n) なので、`persistent_stack`のような
+// 再帰を避けるための`Drop`は要らない。
+
+pub struct Iter<'a, T: 'a> {
+    digits: Option<&'a Digit<T>>,
+    // 前順に辿るため、まだ見ていない部分木を積んでおく。
+    trees: Vec<&'a Tree<T>>,
+    len: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = match self.trees.pop() {
+            Some(tree) => tree,
+            None => {
+                let digit = self.digits?;
+                self.digits = digit.next.as_deref();
+                &*digit.tree
+            }
+        };
+        if let Tree::Node(_, ref left, ref right) = *tree {
+            self.trees.push(right);
+            self.trees.push(left);
+        }
+        self.len -= 1;
+        Some(tree.elem())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use super::{Digit, List, Tree};
+
+    #[test]
+    fn basics() {
+        let list = List::new();
+        assert_eq!(list.head(), None);
+        assert_eq!(list.get(0), None);
+        assert!(list.tail().is_empty());
+
+        let list = list.append(1).append(2).append(3);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.head(), Some(&3));
+        assert_eq!(list.tail().head(), Some(&2));
+        assert_eq!(list.tail().tail().head(), Some(&1));
+        assert_eq!(list.tail().tail().tail().head(), None);
+        assert_eq!(list.get(0), Some(&3));
+        assert_eq!(list.get(2), Some(&1));
+        assert_eq!(list.get(3), None);
+    }
+
+    #[test]
+    fn get_and_iter() {
+        let mut list = List::new();
+        for i in 0..1000 {
+            list = list.append(i);
+            assert_eq!(list.len(), i + 1);
+        }
+        for i in 0..1000 {
+            assert_eq!(list.get(i), Some(&(999 - i)));
+        }
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), (0..1000).rev().collect::<Vec<_>>());
+
+        // `tail`で木が分かれても添字は変わらずに辿れる。
+        let mut rest = list.clone();
+        for i in 0..1000 {
+            assert_eq!(rest.head(), Some(&(999 - i)));
+            let n = rest.len();
+            assert_eq!(rest.get(n / 2), Some(&(n - 1 - n / 2)));
+            assert_eq!(rest.iter().count(), 1000 - i);
+            rest = rest.tail();
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn update_keeps_old_versions() {
+        let mut versions = vec![List::new()];
+        for i in 0..100 {
+            let list = versions.last().unwrap().append(i);
+            versions.push(list);
+        }
+        let base = versions.last().unwrap().clone();
+        let expected: Vec<_> = base.iter().cloned().collect();
+
+        let mut list = base.clone();
+        for i in 0..100 {
+            let next = list.update(i, -(i as i32));
+            assert_eq!(list.get(i), Some(&(99 - i as i32)));
+            assert_eq!(next.get(i), Some(&-(i as i32)));
+            list = next;
+        }
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), (0..100).map(|i| -i).collect::<Vec<_>>());
+
+        // 元のリストとその途中の版は変わっていない。
+        assert_eq!(base.iter().cloned().collect::<Vec<_>>(), expected);
+        for (n, version) in versions.iter().enumerate() {
+            assert_eq!(version.len(), n);
+            assert_eq!(version.head(), n.checked_sub(1).map(|n| n as i32).as_ref());
+        }
+
+        // 更新した版から`tail`や`append`しても、元には影響しない。
+        let updated = base.update(50, 1000).tail().append(-1);
+        assert_eq!(updated.get(0), Some(&-1));
+        assert_eq!(updated.get(50), Some(&1000));
+        assert_eq!(base.get(50), Some(&49));
+    }
+
+    #[test]
+    #[should_panic]
+    fn update_out_of_bounds() {
+        List::new().append(1).update(1, 2);
+    }
+
+    #[test]
+    fn invariants() {
+        let list = (0..20).fold(List::new(), |list, i| list.append(i));
+        list.debug_assert_invariants();
+        list.tail().tail().update(3, 0).debug_assert_invariants();
+
+        let mut broken = list.clone();
+        broken.len += 1;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| broken.debug_assert_invariants()));
+        assert!(result.is_err());
+
+        // 要素が3つある木を、大きさ 1 として繋いだ状態。
+        let leaf = || Arc::new(Tree::Leaf(0));
+        let tree = Arc::new(Tree::Node(0, leaf(), leaf()));
+        let broken = List { head: Some(Arc::new(Digit { size: 1, tree, next: None })), len: 1 };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| broken.debug_assert_invariants()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn to_dot() {
+        // 3つの要素は、大きさ 3 の木1本にまとまる。
+        let list = List::new().append(1).append(2).append(3);
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert!(out.contains("\\nsize=3\\nstrong=1\""));
+        assert_eq!(out.matches(" [label=\"tree\"]").count(), 1);
+        assert_eq!(out.matches(" [label=\"left\"]").count(), 1);
+        assert_eq!(out.matches(" [label=\"right\"]").count(), 1);
+        assert!(out.contains("\\n3\\nstrong=1\""));
+    }
+}
@@ -0,0 +1,524 @@
+// skip list による順序付きマップ。
+// 各ノードは`unsafe_deque`と同じく raw pointer で次のノードを指すが、
+// 「次」を高さの分だけ持っていて、上の段ほど遠くまで飛ばして辿れる。
+// ノードの高さは確率的に決めるので、平均して O(log n) で探索できる。
+
+// 高さを決める乱数は外部から取らず、シード付きの xorshift で作る。
+// 同じシードなら常に同じ形のリストになるので、テストが再現できる。
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::ptr;
+use dot::Dot;
+
+const MAX_LEVEL: usize = 32;
+
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+pub struct SkipList<K, V> {
+    // 各段の先頭ノード。
+    head: [*mut Node<K, V>; MAX_LEVEL],
+    len: usize,
+    rng: XorShift,
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // `next[i]`は i 段目の次のノード。`next.len()`がこのノードの高さ。
+    next: Vec<*mut Node<K, V>>,
+}
+
+pub struct Range<'a, K: 'a, V: 'a> {
+    next: *const Node<K, V>,
+    // 範囲外になる最初のノード (null ならリストの最後まで)。
+    end: *const Node<K, V>,
+    _marker: PhantomData<&'a Node<K, V>>,
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    // 1/2 の確率で1段ずつ高くする。
+    fn random_level(&mut self) -> usize {
+        let bits = self.next() & !(1 << (MAX_LEVEL - 1));
+        bits.trailing_ones() as usize + 1
+    }
+}
+
+impl<K: Ord, V> SkipList<K, V> {
+    pub fn new() -> Self {
+        SkipList::with_seed(DEFAULT_SEED)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        SkipList {
+            head: [ptr::null_mut(); MAX_LEVEL],
+            len: 0,
+            // xorshift は状態が 0 だとずっと 0 を返すので避ける。
+            rng: XorShift(if seed == 0 { DEFAULT_SEED } else { seed }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 各段について「`key`以上の最初のノード」を指しているポインタ
+    // (先頭の配列か、直前のノードの`next`の要素) の場所を返す。
+    // 挿入や削除はここを書き換えればよい。
+    fn find_slots<Q: ?Sized + Ord>(&mut self, key: &Q) -> [*mut *mut Node<K, V>; MAX_LEVEL]
+        where K: Borrow<Q>
+    {
+        let mut slots = [ptr::null_mut(); MAX_LEVEL];
+        let mut nexts: *mut *mut Node<K, V> = self.head.as_mut_ptr();
+        for level in (0..MAX_LEVEL).rev() {
+            unsafe {
+                loop {
+                    let next = *nexts.add(level);
+                    if next.is_null() || (*next).key.borrow() >= key {
+                        break;
+                    }
+                    nexts = (*next).next.as_mut_ptr();
+                }
+                slots[level] = nexts.add(level);
+            }
+        }
+        slots
+    }
+
+    // `is_before`が`true`になるノードを飛ばし、最初に`false`になるノードを返す。
+    fn seek<F: Fn(&K) -> bool>(&self, is_before: F) -> *const Node<K, V> {
+        let mut nexts: *const *mut Node<K, V> = self.head.as_ptr();
+        for level in (0..MAX_LEVEL).rev() {
+            unsafe {
+                loop {
+                    let next = *nexts.add(level);
+                    if next.is_null() || !is_before(&(*next).key) {
+                        break;
+                    }
+                    nexts = (*next).next.as_ptr();
+                }
+            }
+        }
+        unsafe { *nexts }
+    }
+
+    fn find<Q: ?Sized + Ord>(&self, key: &Q) -> *mut Node<K, V>
+        where K: Borrow<Q>
+    {
+        let node = self.seek(|k| k.borrow() < key) as *mut Node<K, V>;
+        if !node.is_null() && unsafe { (*node).key.borrow() == key } {
+            node
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    // 既にあるキーなら値を置き換えて古い値を返す。
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let slots = self.find_slots(&key);
+        unsafe {
+            let found = *slots[0];
+            if !found.is_null() && (*found).key == key {
+                return Some(::std::mem::replace(&mut (*found).value, value));
+            }
+
+            // `slots`の一部は`self.head`を指しているので、`self`全体を借用し直さないように
+            // `rng`フィールドだけを使う。
+            let height = self.rng.random_level();
+            let next = slots[..height].iter().map(|&slot| *slot).collect();
+            let node = Box::into_raw(Box::new(Node { key, value, next }));
+            for &slot in &slots[..height] {
+                *slot = node;
+            }
+        }
+        self.len += 1;
+        self.validate();
+        None
+    }
+
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        let slots = self.find_slots(key);
+        let node = unsafe {
+            let node = *slots[0];
+            if node.is_null() || (*node).key.borrow() != key {
+                return None;
+            }
+            // `node`の高さまでの段では、`slots`は必ず`node`を指している。
+            let node = Box::from_raw(node);
+            for (&slot, &next) in slots.iter().zip(node.next.iter()) {
+                *slot = next;
+            }
+            node
+        };
+        self.len -= 1;
+        self.validate();
+        Some(node.value)
+    }
+
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>
+    {
+        unsafe { self.find(key).as_ref().map(|node| &node.value) }
+    }
+
+    pub fn get_mut<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        unsafe { self.find(key).as_mut().map(|node| &mut node.value) }
+    }
+
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        !self.find(key).is_null()
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        unsafe { self.head[0].as_ref().map(|node| (&node.key, &node.value)) }
+    }
+
+    // 上の段から右端まで辿っていけば O(log n) で最後のノードに着く。
+    pub fn last(&self) -> Option<(&K, &V)> {
+        let mut last: *const Node<K, V> = ptr::null();
+        let mut nexts: *const *mut Node<K, V> = self.head.as_ptr();
+        for level in (0..MAX_LEVEL).rev() {
+            unsafe {
+                while !(*nexts.add(level)).is_null() {
+                    last = *nexts.add(level);
+                    nexts = (*last).next.as_ptr();
+                }
+            }
+        }
+        unsafe { last.as_ref().map(|node| (&node.key, &node.value)) }
+    }
+
+    pub fn range<Q: ?Sized + Ord, R: RangeBounds<Q>>(&self, range: R) -> Range<'_, K, V>
+        where K: Borrow<Q>
+    {
+        let start = match range.start_bound() {
+            Bound::Included(lo) => self.seek(|k| k.borrow() < lo),
+            Bound::Excluded(lo) => self.seek(|k| k.borrow() <= lo),
+            Bound::Unbounded => self.head[0],
+        };
+        let end = match range.end_bound() {
+            Bound::Included(hi) => self.seek(|k| k.borrow() <= hi),
+            Bound::Excluded(hi) => self.seek(|k| k.borrow() < hi),
+            Bound::Unbounded => ptr::null(),
+        };
+        // 開始が終了より後ろにある場合 (`5..3`など) は空にする。
+        // 開始がどのキーよりも大きいと`start`は null になるが、`end`は null とは限らない
+        // (`30..5`など)。そのまま`start`から辿ると`end`に着かないので、これも空にする。
+        let next = if start.is_null() || (!end.is_null() && unsafe { (*start).key > (*end).key }) {
+            end
+        } else {
+            start
+        };
+        Range { next, end, _marker: PhantomData }
+    }
+
+    pub fn iter(&self) -> Range<'_, K, V> {
+        Range { next: self.head[0], end: ptr::null(), _marker: PhantomData }
+    }
+}
+
+impl<K: Ord, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        SkipList::new()
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// - 最下段を辿るとキーが狭義単調増加で並び、その数が`len`と等しい。
+// - 各ノードの高さは 1 以上`MAX_LEVEL`以下。
+// - i 段目を辿ると、最下段のうち高さが i より大きいノードだけを同じ順に通る。
+// `validate`フィーチャーを有効にすると、挿入と削除のたびに呼ばれる。
+impl<K: Ord, V> SkipList<K, V> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut nodes: Vec<*mut Node<K, V>> = Vec::new();
+        let mut cur = self.head[0];
+        while !cur.is_null() {
+            assert!(seen.insert(cur), "cycle: {:p} is reachable twice on level 0", cur);
+            let node = unsafe { &*cur };
+            let height = node.next.len();
+            assert!((1..=MAX_LEVEL).contains(&height), "node {:p} has invalid height {}", cur, height);
+            if let Some(&last) = nodes.last() {
+                assert!(unsafe { &(*last).key } < &node.key, "keys are not strictly increasing at {:p}", cur);
+            }
+            nodes.push(cur);
+            cur = node.next[0];
+        }
+        assert_eq!(self.len, nodes.len(), "`len` does not match the number of nodes");
+
+        for level in 1..MAX_LEVEL {
+            let expected = nodes.iter().filter(|&&node| unsafe { (*node).next.len() } > level);
+            let mut cur = self.head[level];
+            for &node in expected {
+                assert!(cur == node, "level {} does not link {:p} in order", level, node);
+                cur = unsafe { (&*cur).next[level] };
+            }
+            assert!(cur.is_null(), "level {} links {:p}, which is not in level 0", level, cur);
+        }
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// 段ごとの矢印に`L0`、`L1`...とラベルを付ける。`head`の各段も別々の根として書く。
+impl<K: Debug, V: Debug> SkipList<K, V> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        for (i, &node) in self.head.iter().enumerate() {
+            if !node.is_null() {
+                dot.root(&format!("head[{}]", i), node as *const Node<K, V>, &format!("L{}", i));
+            }
+        }
+        let mut cur = self.head[0] as *const Node<K, V>;
+        while !cur.is_null() {
+            let node = unsafe { &*cur };
+            dot.node(cur, &format!("{:?}: {:?}\nheight={}", node.key, node.value, node.next.len()));
+            for (i, &next) in node.next.iter().enumerate() {
+                dot.edge(cur, next as *const Node<K, V>, &format!("L{}", i));
+            }
+            cur = node.next[0];
+        }
+        dot.finish()
+    }
+}
+
+// 最下段はすべてのノードを繋いでいるので、それを辿って解放する。
+impl<K, V> Drop for SkipList<K, V> {
+    fn drop(&mut self) {
+        // キーや値の drop が panic しても、残りのノードは`DropGuard`が解放し続ける。
+        // (その途中でさらに panic すると abort になる。)
+        struct DropGuard<K, V>(*mut Node<K, V>);
+        impl<K, V> Drop for DropGuard<K, V> {
+            fn drop(&mut self) {
+                while !self.0.is_null() {
+                    let node = unsafe { Box::from_raw(self.0) };
+                    self.0 = node.next[0];
+                }
+            }
+        }
+        let mut rest = DropGuard(self.head[0]);
+        while !rest.0.is_null() {
+            let node = unsafe { Box::from_raw(rest.0) };
+            rest.0 = node.next[0];
+        }
+    }
+}
+
+// ノードは`SkipList`だけが所有しているので、`Box`で持つ場合と同じ条件で送れる。
+unsafe impl<K: Send, V: Send> Send for SkipList<K, V> {}
+unsafe impl<K: Sync, V: Sync> Sync for SkipList<K, V> {}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.end {
+            return None;
+        }
+        unsafe {
+            let node = &*self.next;
+            self.next = node.next[0];
+            Some((&node.key, &node.value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+    use std::panic::{self, AssertUnwindSafe};
+    use super::SkipList;
+
+    #[test]
+    fn basics() {
+        let mut list = SkipList::new();
+        assert_eq!(list.first(), None);
+        assert_eq!(list.last(), None);
+        assert_eq!(list.remove(&1), None);
+
+        assert_eq!(list.insert(3, "c"), None);
+        assert_eq!(list.insert(1, "a"), None);
+        assert_eq!(list.insert(2, "b"), None);
+        assert_eq!(list.insert(2, "B"), Some("b"));
+        assert_eq!(list.len(), 3);
+
+        assert_eq!(list.get(&2), Some(&"B"));
+        assert_eq!(list.get(&4), None);
+        *list.get_mut(&1).unwrap() = "A";
+        assert_eq!(list.first(), Some((&1, &"A")));
+        assert_eq!(list.last(), Some((&3, &"c")));
+
+        assert_eq!(list.remove(&3), Some("c"));
+        assert_eq!(list.remove(&3), None);
+        assert_eq!(list.last(), Some((&2, &"B")));
+        assert!(!list.contains_key(&3));
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![(&1, &"A"), (&2, &"B")]);
+    }
+
+    #[test]
+    // `30..5`のような逆向きの範囲もわざと渡す。
+    #[allow(clippy::reversed_empty_ranges)]
+    fn range() {
+        let mut list = SkipList::new();
+        for i in 0..10 {
+            list.insert(i * 2, ());
+        }
+        let keys = |r: Vec<(&i32, &())>| r.into_iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(keys(list.range(3..9).collect()), vec![4, 6, 8]);
+        assert_eq!(keys(list.range(4..=8).collect()), vec![4, 6, 8]);
+        assert_eq!(keys(list.range(..3).collect()), vec![0, 2]);
+        assert_eq!(keys(list.range(15..).collect()), vec![16, 18]);
+        assert_eq!(keys(list.range((Bound::Excluded(4), Bound::Excluded(8))).collect()), vec![6]);
+        assert_eq!(keys(list.range(7..7).collect()), vec![]);
+        assert_eq!(keys(list.range((Bound::Included(8), Bound::Excluded(3))).collect()), vec![]);
+        assert_eq!(keys(list.range(30..).collect()), vec![]);
+        // 開始が最大のキーより大きく、終了はそれより小さい。
+        assert_eq!(keys(list.range(30..5).collect()), vec![]);
+        assert_eq!(keys(list.range((Bound::Excluded(18), Bound::Included(20))).collect()), vec![]);
+        assert_eq!(keys(list.range((Bound::Included(19), Bound::Excluded(25))).collect()), vec![]);
+        assert_eq!(keys(list.range((Bound::Excluded(18), Bound::Unbounded)).collect()), vec![]);
+        assert_eq!(keys(list.range((Bound::Included(30), Bound::Included(0))).collect()), vec![]);
+    }
+
+    #[test]
+    fn borrowed_keys() {
+        let mut list = SkipList::new();
+        list.insert(String::from("b"), 2);
+        list.insert(String::from("a"), 1);
+        assert_eq!(list.get("a"), Some(&1));
+        assert_eq!(list.range::<str, _>((Bound::Included("a"), Bound::Excluded("b"))).count(), 1);
+        assert_eq!(list.remove("b"), Some(2));
+    }
+
+    #[test]
+    fn deterministic_levels() {
+        let heights = |seed| {
+            let mut list = SkipList::with_seed(seed);
+            for i in 0..100 {
+                list.insert(i, ());
+            }
+            let mut heights = Vec::new();
+            let mut cur = list.head[0];
+            while !cur.is_null() {
+                let node = unsafe { &*cur };
+                heights.push(node.next.len());
+                cur = node.next[0];
+            }
+            heights
+        };
+        assert_eq!(heights(42), heights(42));
+        assert_ne!(heights(42), heights(43));
+        assert!(heights(42).iter().any(|&h| h > 1));
+    }
+
+    #[test]
+    fn matches_btree_map() {
+        let mut list = SkipList::with_seed(7);
+        let mut model = BTreeMap::new();
+
+        let mut seed: u32 = 99;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) & 0x7fff
+        };
+        for i in 0..20_000 {
+            let key = rand() % 500;
+            match rand() % 6 {
+                0 | 1 => assert_eq!(list.insert(key, i), model.insert(key, i)),
+                2 => assert_eq!(list.remove(&key), model.remove(&key)),
+                3 => assert_eq!(list.get(&key), model.get(&key)),
+                4 => {
+                    let hi = key + rand() % 50;
+                    assert!(list.range(key..hi).eq(model.range(key..hi)));
+                }
+                _ => {
+                    assert_eq!(list.first(), model.iter().next());
+                    assert_eq!(list.last(), model.iter().next_back());
+                }
+            }
+            assert_eq!(list.len(), model.len());
+        }
+        assert!(list.iter().eq(model.iter()));
+    }
+
+    // 挿入のたびにリスト全体を確かめると終わらないので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn long_list_drop() {
+        let mut list = SkipList::new();
+        for i in 0..100_000 {
+            list.insert(i, i);
+        }
+        drop(list);
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list = SkipList::with_seed(7);
+        for i in 0..50 {
+            list.insert(i, ());
+        }
+        list.debug_assert_invariants();
+
+        // キーの順序が崩れた状態。
+        let first = list.head[0];
+        unsafe { (*first).key = 100 };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        unsafe { (*first).key = 0 };
+
+        // 上の段が最下段にあるノードを飛ばしている状態。
+        let level = (1..super::MAX_LEVEL).find(|&l| !list.head[l].is_null()).unwrap();
+        let top = list.head[level];
+        list.head[level] = unsafe { (&*top).next[level] };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.head[level] = top;
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn to_dot() {
+        // 高さは乱数で決まるので、種を固定する。
+        let mut list = SkipList::with_seed(7);
+        list.insert(1, "a");
+        list.insert(2, "b");
+        list.insert(3, "c");
+        let out = list.to_dot();
+        // どの段も先頭から辿れ、一番下の段は全要素を順に繋いでいる。
+        assert!(out.contains("\"head[0]\" -> "));
+        assert_eq!(out.matches(" [label=\"L0\"]").count(), 3);
+        assert!(out.contains("\\n1: \\\"a\\\"\\nheight="));
+        assert!(out.contains("\\n3: \\\"c\\\"\\nheight="));
+    }
+}
@@ -0,0 +1,478 @@
+// 複数のスレッドから`&self`で push/pop/peek できる、ロックを使わないスタック (Treiber stack)。
+// `ok_stack::List`と同じく先頭にノードを繋ぐ片方向リストで、先頭の付け替えを`AtomicPtr`の CAS で行う。
+
+// 要素はノードの中に`Arc<T>`で持ち、`pop`と`peek`はその複製を返す。
+// `pop`が要素をノードから持ち出してしまうと、同じノードを`peek`している他のスレッドは
+// 解放済みの要素を読むかもしれない。それを避けるために`pop`が`peek`の終わりを待つと、
+// `peek`中のスレッドが止まれば`pop`も止まってしまう。
+// ノードが持っている`Arc`はノードを解放する時に drop するので、hazard がノードを守っている間は
+// 要素も生きている。`peek`は`Arc`を複製するだけなので、`T::clone`がどれだけ遅くても`pop`を待たせない。
+// そのかわり、要素は`pop`した側の`Arc`とノードの両方が無くなるまで drop されない。
+
+// ロックがないので、pop したノードをすぐに解放する事はできない。
+// 他のスレッドがちょうどそのノードの`next`を読もうとしているかもしれないからだ。
+// そこで hazard pointer を使う。ノードを読むスレッドは、読む前にそのアドレスを自分の hazard に書いておき、
+// pop したスレッドはノードを「退役済み」として溜めておいて、どの hazard にも載っていないものだけを解放する。
+// hazard を書く`Record`はスタックごとの連結リストで、操作のたびに空いているものを借りる。
+
+// ABA 問題について: pop は「先頭が`A`で、その次が`B`」と読んでから`A`→`B`の CAS を行う。
+// 間に`A`が pop されて解放され、同じアドレスに別のノードが確保されて push されると、
+// 先頭はまた`A`に見えるので CAS が成功し、既に無い`B`を先頭にしてしまう。
+// hazard に`A`を載せたあとで先頭がまだ`A`である事を確かめているので、CAS を行うまで`A`は解放されず、
+// 同じアドレスのノードが作られる事もない。push は常に新しいノードを確保し、pop したノードを再び push する事はないので、
+// 先頭が`A`のままなら`A`は一度も pop されていない。`A.next`は push の前に書いたきり変わらないので、`B`は正しい。
+
+use std::cell::UnsafeCell;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use dot::Dot;
+
+pub struct TreiberStack<T> {
+    head: AtomicPtr<Node<T>>,
+    // 一度作った`Record`は`TreiberStack`が drop されるまで解放しない。
+    records: AtomicPtr<Record<T>>,
+    // `T`を所有している事をドロップチェッカーに伝える。
+    _marker: PhantomData<Arc<T>>,
+}
+
+struct Node<T> {
+    // ノードを解放する時に drop する。それまでは`pop`や`peek`がいつでも複製してよい。
+    elem: Arc<T>,
+    next: *mut Node<T>,
+}
+
+// hazard pointer を書いておく場所。`active`を立てたスレッドだけが使う。
+struct Record<T> {
+    // 繋いだあとは変わらない。
+    next: *mut Record<T>,
+    active: AtomicBool,
+    // `next`や要素を読むために守っているノード。
+    hazard: AtomicPtr<Node<T>>,
+    // pop したが、まだ解放していないノード。`active`を立てたスレッドだけが触る。
+    retired: UnsafeCell<Vec<*mut Node<T>>>,
+}
+
+// 退役済みのノードがこれだけ溜まったら、hazard を調べて解放できるものを解放する。
+const RETIRE_THRESHOLD: usize = 32;
+
+// ノードの`Arc<T>`は複製されて複数のスレッドに渡るので、`Arc<T>`を送れる条件 (`T: Send + Sync`) が要る。
+unsafe impl<T: Send + Sync> Send for TreiberStack<T> {}
+unsafe impl<T: Send + Sync> Sync for TreiberStack<T> {}
+
+impl<T> TreiberStack<T> {
+    pub fn new() -> Self {
+        TreiberStack { head: AtomicPtr::new(ptr::null_mut()), records: AtomicPtr::new(ptr::null_mut()), _marker: PhantomData }
+    }
+
+    pub fn push(&self, elem: T) {
+        let node = Box::into_raw(Box::new(Node { elem: Arc::new(elem), next: ptr::null_mut() }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // まだ誰にも見えていないノードなので、そのまま書き換えてよい。
+            unsafe { (*node).next = head };
+            // 成功したら`node`の中身が他のスレッドに見えるよう`Release`で公開する。
+            match self.head.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<Arc<T>> {
+        let guard = self.acquire();
+        loop {
+            let head = self.protect(&guard.record.hazard)?;
+            // `head`は hazard で守られているので、まだ解放されていない。
+            let next = unsafe { (*head).next };
+            if self.head.compare_exchange(head, next, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                // 要素は持ち出さずに複製する。ノードの`Arc`は、解放する時に drop される。
+                let elem = unsafe { Arc::clone(&(*head).elem) };
+                guard.record.hazard.store(ptr::null_mut(), Ordering::Release);
+                unsafe { guard.retire(head, self) };
+                return Some(elem);
+            }
+        }
+    }
+
+    // 先頭の要素を返す。返した時点で、既に他のスレッドに pop されているかもしれない。
+    // hazard で守るのは`Arc`を複製する間だけなので、他のスレッドの`pop`を待たせる事はない。
+    pub fn peek(&self) -> Option<Arc<T>> {
+        let guard = self.acquire();
+        let head = self.protect(&guard.record.hazard)?;
+        Some(unsafe { Arc::clone(&(*head).elem) })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+
+    // 先頭を`slot`に載せ、載せたあとも先頭のままである事を確かめてから返す。
+    // 確かめた時点で先頭だったなら、まだ退役していないので、以降は`slot`がノードを守る。
+    fn protect(&self, slot: &AtomicPtr<Node<T>>) -> Option<*mut Node<T>> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            if head.is_null() {
+                return None;
+            }
+            slot.store(head, Ordering::SeqCst);
+            let current = self.head.load(Ordering::SeqCst);
+            if current == head {
+                return Some(head);
+            }
+            head = current;
+        }
+    }
+
+    // 空いている`Record`を借りる。なければ新しく作って先頭に繋ぐ。
+    fn acquire(&self) -> RecordGuard<'_, T> {
+        for record in self.records() {
+            if !record.active.load(Ordering::Relaxed)
+                && record.active.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+            {
+                return RecordGuard { record };
+            }
+        }
+        let record = Box::into_raw(Box::new(Record {
+            next: ptr::null_mut(),
+            active: AtomicBool::new(true),
+            hazard: AtomicPtr::new(ptr::null_mut()),
+            retired: UnsafeCell::new(Vec::new()),
+        }));
+        let mut head = self.records.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*record).next = head };
+            match self.records.compare_exchange_weak(head, record, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return RecordGuard { record: unsafe { &*record } },
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn records(&self) -> Records<'_, T> {
+        Records { next: self.records.load(Ordering::Acquire), _marker: PhantomData }
+    }
+}
+
+// 先頭から辿った構造を DOT 形式で出力する (デバッグ用)。
+// `&mut self`なので他のスレッドは操作中ではなく、辿っている間にノードが pop されて解放される事はない。
+impl<T: Debug> TreiberStack<T> {
+    pub fn to_dot(&mut self) -> String {
+        let mut dot = Dot::new();
+        let head = *self.head.get_mut();
+        dot.root("head", head, "");
+        let mut cur = head;
+        while !cur.is_null() {
+            let node = unsafe { &*cur };
+            dot.node(cur, &format!("{:?}\nstrong={}", node.elem, Arc::strong_count(&node.elem)));
+            dot.edge(cur, node.next, "next");
+            cur = node.next;
+        }
+        dot.finish()
+    }
+}
+
+impl<T> Default for TreiberStack<T> {
+    fn default() -> Self {
+        TreiberStack::new()
+    }
+}
+
+// `&mut self`なので、他のスレッドは操作中ではなく、hazard も残っていない。
+impl<T> Drop for TreiberStack<T> {
+    fn drop(&mut self) {
+        let mut cur = *self.head.get_mut();
+        while !cur.is_null() {
+            let node = unsafe { Box::from_raw(cur) };
+            cur = node.next;
+        }
+        let mut cur = *self.records.get_mut();
+        while !cur.is_null() {
+            let record = unsafe { Box::from_raw(cur) };
+            for &node in unsafe { &*record.retired.get() } {
+                drop(unsafe { Box::from_raw(node) });
+            }
+            cur = record.next;
+        }
+    }
+}
+
+struct Records<'a, T: 'a> {
+    next: *mut Record<T>,
+    _marker: PhantomData<&'a Record<T>>,
+}
+
+impl<'a, T> Iterator for Records<'a, T> {
+    type Item = &'a Record<T>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = unsafe { self.next.as_ref()? };
+        self.next = record.next;
+        Some(record)
+    }
+}
+
+// 借りた`Record`。drop すると hazard を外して返す。
+struct RecordGuard<'a, T: 'a> {
+    record: &'a Record<T>,
+}
+
+impl<'a, T> RecordGuard<'a, T> {
+    // `node`は先頭から外したノード。溜まっていたら、どの hazard にも載っていないものを解放する。
+    unsafe fn retire(&self, node: *mut Node<T>, stack: &TreiberStack<T>) {
+        let retired = &mut *self.record.retired.get();
+        retired.push(node);
+        if retired.len() < RETIRE_THRESHOLD {
+            return;
+        }
+        let hazards: Vec<*mut Node<T>> = stack.records()
+            .map(|r| r.hazard.load(Ordering::SeqCst))
+            .filter(|p| !p.is_null())
+            .collect();
+        retired.retain(|&node| {
+            if hazards.contains(&node) {
+                return true;
+            }
+            drop(Box::from_raw(node));
+            false
+        });
+    }
+}
+
+impl<'a, T> Drop for RecordGuard<'a, T> {
+    fn drop(&mut self) {
+        self.record.hazard.store(ptr::null_mut(), Ordering::Release);
+        self.record.active.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use super::{TreiberStack, RETIRE_THRESHOLD};
+
+    #[test]
+    fn basics() {
+        let stack = TreiberStack::new();
+        assert_eq!(stack.pop(), None);
+        assert_eq!(stack.peek(), None);
+        assert!(stack.is_empty());
+
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.peek(), Some(Arc::new(3)));
+        assert_eq!(stack.pop(), Some(Arc::new(3)));
+        assert_eq!(stack.pop(), Some(Arc::new(2)));
+
+        stack.push(4);
+        stack.push(5);
+        assert_eq!(stack.pop(), Some(Arc::new(5)));
+        assert_eq!(stack.pop(), Some(Arc::new(4)));
+        assert_eq!(stack.pop(), Some(Arc::new(1)));
+        assert_eq!(stack.pop(), None);
+        assert!(stack.is_empty());
+    }
+
+    // 要素は取り出されたものも残っていたものも、ちょうど1回ずつ drop される。
+    // 取り出した要素は、退役済みのノードが解放されるまで drop されない。
+    #[test]
+    fn drop_once() {
+        struct Counted<'a>(&'a AtomicUsize);
+        impl<'a> Drop for Counted<'a> {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let drops = AtomicUsize::new(0);
+        {
+            let stack = TreiberStack::new();
+            for _ in 0..100 {
+                stack.push(Counted(&drops));
+            }
+            for _ in 0..60 {
+                drop(stack.pop());
+            }
+            let retired = unsafe { (*stack.records().next().unwrap().retired.get()).len() };
+            assert_eq!(drops.load(Ordering::SeqCst), 60 - retired);
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut stack = TreiberStack::new();
+        assert!(!stack.to_dot().contains("->"));
+        stack.push(1);
+        stack.push(2);
+        // pop したノードは退役済みになるだけで、先頭からは辿れない。
+        stack.push(3);
+        stack.pop();
+        let out = stack.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert!(out.contains("\\n2\\nstrong=1\"]"));
+        assert!(!out.contains("\\n3\\n"));
+        // 取り出した`Arc`を持っている間は、ノードの`Arc`と合わせて2つになる。
+        let top = stack.peek().unwrap();
+        assert!(stack.to_dot().contains("\\n2\\nstrong=2\"]"));
+        drop(top);
+    }
+
+    // 退役済みのノードは溜まりすぎる前に解放される。
+    #[test]
+    fn reclamation() {
+        let stack = TreiberStack::new();
+        for i in 0..1000 {
+            stack.push(i);
+            stack.pop();
+        }
+        let record = stack.records().next().unwrap();
+        assert_eq!(stack.records().count(), 1);
+        assert!(unsafe { (*record.retired.get()).len() } < RETIRE_THRESHOLD);
+    }
+
+    #[test]
+    fn concurrent_push_pop() {
+        const THREADS: usize = 8;
+        const OPS: usize = 5000;
+        let stack = TreiberStack::new();
+        let barrier = Barrier::new(THREADS);
+
+        // 各スレッドが自分の値を push しながら pop する。取り出した値に重複も欠けもない。
+        let popped: Vec<Vec<Arc<usize>>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..THREADS).map(|t| {
+                let (stack, barrier) = (&stack, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    let mut popped = Vec::new();
+                    for i in 0..OPS {
+                        stack.push(t * OPS + i);
+                        if i % 2 == 1 {
+                            popped.extend(stack.pop());
+                            popped.extend(stack.pop());
+                        }
+                    }
+                    popped
+                })
+            }).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut seen = HashSet::new();
+        for value in popped.into_iter().flatten() {
+            assert!(seen.insert(*value), "{} was popped twice", value);
+        }
+        while let Some(value) = stack.pop() {
+            assert!(seen.insert(*value), "{} was popped twice", value);
+        }
+        assert_eq!(seen.len(), THREADS * OPS);
+    }
+
+    // 同じスレッドが push した値は、後から push したものほど先に出てくる (LIFO)。
+    #[test]
+    fn concurrent_lifo_per_thread() {
+        const THREADS: usize = 4;
+        const OPS: usize = 5000;
+        let stack = TreiberStack::new();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let stack = &stack;
+                s.spawn(move || {
+                    for i in 0..OPS {
+                        stack.push((t, i));
+                    }
+                });
+            }
+        });
+
+        let mut last = [OPS; THREADS];
+        while let Some(&(t, i)) = stack.pop().as_deref() {
+            assert!(i < last[t]);
+            last[t] = i;
+        }
+        assert!(last.iter().all(|&i| i == 0));
+    }
+
+    // 複製に時間のかかる要素。`released`が立つまで`clone`から戻らない。
+    struct Slow<'a>(String, &'a AtomicBool);
+    impl<'a> Clone for Slow<'a> {
+        fn clone(&self) -> Self {
+            while !self.1.load(Ordering::Acquire) {
+                thread::yield_now();
+            }
+            Slow(self.0.clone(), self.1)
+        }
+    }
+
+    // `peek`した要素を複製している最中でも、同じ要素を`pop`できる。
+    // `pop`が複製の終わりを待つ作りだと、ここで止まってしまう。
+    #[test]
+    fn peek_does_not_block_pop() {
+        let released = AtomicBool::new(false);
+        let peeked = AtomicBool::new(false);
+        let stack = TreiberStack::new();
+        stack.push(Slow("a".to_string(), &released));
+        thread::scope(|s| {
+            let cloner = s.spawn(|| {
+                let top = stack.peek().unwrap();
+                peeked.store(true, Ordering::Release);
+                (*top).clone().0
+            });
+            while !peeked.load(Ordering::Acquire) {
+                thread::yield_now();
+            }
+            assert_eq!(stack.pop().unwrap().0, "a");
+            assert!(stack.is_empty());
+            released.store(true, Ordering::Release);
+            assert_eq!(cloner.join().unwrap(), "a");
+        });
+    }
+
+    // 遅い`Clone`で`peek`した要素を複製するスレッドと、pop するスレッドを同時に動かす。
+    // pop 側は複製を待たずに進み、複製する側は解放済みの`String`を読まない。
+    #[test]
+    fn concurrent_peek() {
+        const THREADS: usize = 4;
+        const OPS: usize = 3000;
+        let released = AtomicBool::new(false);
+        let stack: TreiberStack<Slow> = TreiberStack::new();
+        let pops = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                let stack = &stack;
+                s.spawn(move || {
+                    for _ in 0..OPS {
+                        if let Some(peeked) = stack.peek() {
+                            assert!((*peeked).clone().0.contains('-'));
+                        }
+                    }
+                });
+            }
+            let poppers: Vec<_> = (0..THREADS).map(|t| {
+                let (stack, released, pops) = (&stack, &released, &pops);
+                s.spawn(move || {
+                    for i in 0..OPS {
+                        stack.push(Slow(format!("{}-{}", t, i), released));
+                        if i % 3 != 0 {
+                            assert!(stack.pop().unwrap().0.contains('-'));
+                            pops.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            }).collect();
+            // 複製はまだ1つも終わっていないが、pop は全部終わる。
+            for popper in poppers {
+                popper.join().unwrap();
+            }
+            released.store(true, Ordering::Release);
+        });
+        assert_eq!(pops.load(Ordering::Relaxed), THREADS * (OPS - OPS / 3));
+        assert_eq!(std::iter::from_fn(|| stack.pop()).count(), THREADS * OPS / 3);
+    }
+}
@@ -0,0 +1,814 @@
+// unrolled linked list。
+// 1つのノードに要素を最大`N`個まで配列で詰めておく双方向リスト。
+// `ok_stack`や`unsafe_deque`は要素ごとにポインタを辿るのでキャッシュ効率が悪いが、
+// こうすればポインタを辿るのは`N`要素に1回で済む。
+
+// ノードの配列は先頭から`len`個だけが初期化済み。
+// 空のノードはリストに残さない (`head`/`tail`が null なのはリストが空のときだけ)。
+// 満杯のノードに挿入するときは半分に分け、要素が減ったノードは隣と合わせられるなら合わせる。
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::slice;
+use dot::Dot;
+
+pub struct List<T, const N: usize = 16> {
+    head: *mut Node<T, N>,
+    tail: *mut Node<T, N>,
+    len: usize,
+    _marker: PhantomData<Box<Node<T, N>>>,
+}
+
+struct Node<T, const N: usize> {
+    elems: [MaybeUninit<T>; N],
+    len: usize,
+    prev: *mut Node<T, N>,
+    next: *mut Node<T, N>,
+}
+
+pub struct Iter<'a, T: 'a, const N: usize = 16> {
+    front: *const Node<T, N>,
+    front_idx: usize,
+    back: *const Node<T, N>,
+    // `back`の中でまだ返していない範囲の終わり (この位置は含まない)。
+    back_idx: usize,
+    remaining: usize,
+    _marker: PhantomData<&'a T>,
+}
+
+pub struct IterMut<'a, T: 'a, const N: usize = 16> {
+    front: *mut Node<T, N>,
+    front_idx: usize,
+    back: *mut Node<T, N>,
+    back_idx: usize,
+    remaining: usize,
+    _marker: PhantomData<&'a mut T>,
+}
+
+pub struct IntoIter<T, const N: usize = 16>(List<T, N>);
+
+impl<T, const N: usize> Node<T, N> {
+    fn new() -> Box<Self> {
+        Box::new(Node {
+            elems: [const { MaybeUninit::uninit() }; N],
+            len: 0,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    fn as_ptr(&self) -> *const T {
+        self.elems.as_ptr() as *const T
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut T {
+        self.elems.as_mut_ptr() as *mut T
+    }
+
+    fn as_slice(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+
+    // 満杯でない事は呼び出し側が確認する。
+    fn insert(&mut self, idx: usize, elem: T) {
+        debug_assert!(idx <= self.len && !self.is_full());
+        unsafe {
+            let p = self.as_mut_ptr().add(idx);
+            ptr::copy(p, p.add(1), self.len - idx);
+            ptr::write(p, elem);
+        }
+        self.len += 1;
+    }
+
+    fn remove(&mut self, idx: usize) -> T {
+        debug_assert!(idx < self.len);
+        self.len -= 1;
+        unsafe {
+            let p = self.as_mut_ptr().add(idx);
+            let elem = ptr::read(p);
+            ptr::copy(p.add(1), p, self.len - idx);
+            elem
+        }
+    }
+
+    // `at`以降の要素を新しいノードに移して返す。
+    fn split_off(&mut self, at: usize) -> Box<Self> {
+        let mut other = Node::new();
+        let count = self.len - at;
+        unsafe {
+            ptr::copy_nonoverlapping(self.as_ptr().add(at), other.as_mut_ptr(), count);
+        }
+        other.len = count;
+        self.len = at;
+        other
+    }
+
+    // `other`の要素をすべて末尾に移す。合計が`N`以下である事は呼び出し側が確認する。
+    fn append(&mut self, other: &mut Self) {
+        debug_assert!(self.len + other.len <= N);
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().add(self.len), other.len);
+        }
+        self.len += other.len;
+        other.len = 0;
+    }
+}
+
+// イテレータは返した要素への参照を持ったまま次の要素へ進むので、
+// ノード全体への参照を作らずに要素の位置を求める。
+unsafe fn elem_ptr<T, const N: usize>(node: *mut Node<T, N>, idx: usize) -> *mut T {
+    (ptr::addr_of_mut!((*node).elems) as *mut T).add(idx)
+}
+
+impl<T, const N: usize> Drop for Node<T, N> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.as_mut_slice()) };
+    }
+}
+
+impl<T, const N: usize> List<T, N> {
+    pub fn new() -> Self {
+        // 満杯のノードを2つに分けられるように、最低2つは入るようにする。
+        assert!(N >= 2, "each node must hold at least 2 elements");
+        List { head: ptr::null_mut(), tail: ptr::null_mut(), len: 0, _marker: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_back(&mut self, elem: T) {
+        if self.tail.is_null() || unsafe { (*self.tail).is_full() } {
+            let tail = self.tail;
+            self.link_after(tail, Node::new());
+        }
+        unsafe {
+            let tail = &mut *self.tail;
+            tail.insert(tail.len, elem);
+        }
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn push_front(&mut self, elem: T) {
+        if self.head.is_null() || unsafe { (*self.head).is_full() } {
+            self.link_before_head(Node::new());
+        }
+        unsafe { (*self.head).insert(0, elem) };
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.tail.is_null() {
+            return None;
+        }
+        let tail = self.tail;
+        let elem = unsafe { (*tail).remove((*tail).len - 1) };
+        self.len -= 1;
+        if unsafe { (*tail).len } == 0 {
+            unsafe { self.unlink(tail) };
+        }
+        self.validate();
+        Some(elem)
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.head.is_null() {
+            return None;
+        }
+        let head = self.head;
+        let elem = unsafe { (*head).remove(0) };
+        self.len -= 1;
+        if unsafe { (*head).len } == 0 {
+            unsafe { self.unlink(head) };
+        }
+        self.validate();
+        Some(elem)
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        unsafe { self.head.as_ref().map(|node| &node.as_slice()[0]) }
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        unsafe { self.tail.as_ref().map(|node| &node.as_slice()[node.len - 1]) }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&T> {
+        if idx >= self.len {
+            return None;
+        }
+        let (node, offset) = self.locate(idx);
+        unsafe { Some(&(*node).as_slice()[offset]) }
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut T> {
+        if idx >= self.len {
+            return None;
+        }
+        let (node, offset) = self.locate(idx);
+        unsafe { Some(&mut (*node).as_mut_slice()[offset]) }
+    }
+
+    // `idx > len`の場合は panic する (`Vec::insert`と同じ)。
+    pub fn insert(&mut self, idx: usize, elem: T) {
+        assert!(idx <= self.len, "index out of bounds");
+        if idx == self.len {
+            return self.push_back(elem);
+        }
+        let (mut node, mut offset) = self.locate(idx);
+        unsafe {
+            if (*node).is_full() {
+                // 半分に分けて、挿入位置を含む方に入れる。
+                let half = N / 2;
+                let new_node = (*node).split_off(half);
+                let new_node = self.link_after(node, new_node);
+                if offset >= half {
+                    node = new_node;
+                    offset -= half;
+                }
+            }
+            (*node).insert(offset, elem);
+        }
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<T> {
+        if idx >= self.len {
+            return None;
+        }
+        let (node, offset) = self.locate(idx);
+        let elem = unsafe { (*node).remove(offset) };
+        self.len -= 1;
+        unsafe { self.rebalance(node) };
+        self.validate();
+        Some(elem)
+    }
+
+    // 要素の drop が panic しても、リストは先に空にしてあるので壊れない。
+    // 残りのノードは`DropGuard`が解放し続ける (その途中でさらに panic すると abort になる)。
+    pub fn clear(&mut self) {
+        struct DropGuard<T, const N: usize>(*mut Node<T, N>);
+        impl<T, const N: usize> Drop for DropGuard<T, N> {
+            fn drop(&mut self) {
+                while !self.0.is_null() {
+                    let node = unsafe { Box::from_raw(self.0) };
+                    self.0 = node.next;
+                }
+            }
+        }
+        let mut rest = DropGuard(self.head);
+        self.head = ptr::null_mut();
+        self.tail = ptr::null_mut();
+        self.len = 0;
+        while !rest.0.is_null() {
+            let node = unsafe { Box::from_raw(rest.0) };
+            rest.0 = node.next;
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, N> {
+        Iter {
+            front: self.head,
+            front_idx: 0,
+            back: self.tail,
+            back_idx: unsafe { self.tail.as_ref().map_or(0, |node| node.len) },
+            remaining: self.len,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, N> {
+        IterMut {
+            front: self.head,
+            front_idx: 0,
+            back: self.tail,
+            back_idx: unsafe { self.tail.as_ref().map_or(0, |node| node.len) },
+            remaining: self.len,
+            _marker: PhantomData,
+        }
+    }
+
+    // `idx`番目の要素があるノードと、その中での位置。近い方の端から辿る。
+    fn locate(&self, mut idx: usize) -> (*mut Node<T, N>, usize) {
+        debug_assert!(idx < self.len);
+        unsafe {
+            if idx < self.len / 2 {
+                let mut node = self.head;
+                while idx >= (*node).len {
+                    idx -= (*node).len;
+                    node = (*node).next;
+                }
+                (node, idx)
+            } else {
+                let mut node = self.tail;
+                let mut from_back = self.len - 1 - idx;
+                while from_back >= (*node).len {
+                    from_back -= (*node).len;
+                    node = (*node).prev;
+                }
+                (node, (*node).len - 1 - from_back)
+            }
+        }
+    }
+
+    // `prev`の後ろ (`prev`が null なら先頭) に`node`を繋ぐ。
+    fn link_after(&mut self, prev: *mut Node<T, N>, node: Box<Node<T, N>>) -> *mut Node<T, N> {
+        if prev.is_null() {
+            return self.link_before_head(node);
+        }
+        let node = Box::into_raw(node);
+        unsafe {
+            let next = (*prev).next;
+            (*node).prev = prev;
+            (*node).next = next;
+            (*prev).next = node;
+            if next.is_null() {
+                self.tail = node;
+            } else {
+                (*next).prev = node;
+            }
+        }
+        node
+    }
+
+    fn link_before_head(&mut self, node: Box<Node<T, N>>) -> *mut Node<T, N> {
+        let node = Box::into_raw(node);
+        unsafe {
+            (*node).next = self.head;
+            if self.head.is_null() {
+                self.tail = node;
+            } else {
+                (*self.head).prev = node;
+            }
+        }
+        self.head = node;
+        node
+    }
+
+    // `node`をリストから外して解放する。
+    unsafe fn unlink(&mut self, node: *mut Node<T, N>) {
+        let node = Box::from_raw(node);
+        if node.prev.is_null() {
+            self.head = node.next;
+        } else {
+            (*node.prev).next = node.next;
+        }
+        if node.next.is_null() {
+            self.tail = node.prev;
+        } else {
+            (*node.next).prev = node.prev;
+        }
+    }
+
+    // 要素が減った`node`を、隣のノードと合わせられるなら合わせる。
+    // 半分以上埋まっているノードはそのままにしておき、挿入と削除を繰り返したときに
+    // 分割と結合が交互に起きないようにする。
+    unsafe fn rebalance(&mut self, node: *mut Node<T, N>) {
+        if (*node).len == 0 {
+            return self.unlink(node);
+        }
+        if (*node).len >= N / 2 {
+            return;
+        }
+        let next = (*node).next;
+        if !next.is_null() && (*node).len + (*next).len <= N {
+            (*node).append(&mut *next);
+            return self.unlink(next);
+        }
+        let prev = (*node).prev;
+        if !prev.is_null() && (*prev).len + (*node).len <= N {
+            (*prev).append(&mut *node);
+            self.unlink(node);
+        }
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// - `head`から`next`で辿ると重複なく`tail`に着き、隣り合うノードの`prev`と`next`が互いを指している。
+// - どのノードも空ではなく、要素数は`N`以下で、その合計が`len`と等しい。
+// `validate`フィーチャーを有効にすると、リストを変更する操作のたびに呼ばれる。
+impl<T, const N: usize> List<T, N> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut total = 0;
+        let mut prev: *mut Node<T, N> = ptr::null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() {
+            assert!(seen.insert(cur), "cycle: {:p} is reachable twice from `head`", cur);
+            let node = unsafe { &*cur };
+            assert!(node.prev == prev, "`prev` of {:p} is {:p}, expected {:p}", cur, node.prev, prev);
+            assert!(node.len > 0, "empty node {:p} is left in the list", cur);
+            assert!(node.len <= N, "node {:p} has {} elements, more than {}", cur, node.len, N);
+            total += node.len;
+            prev = cur;
+            cur = node.next;
+        }
+        assert!(self.tail == prev, "`tail` is {:p}, but the last node is {:p}", self.tail, prev);
+        assert_eq!(self.len, total, "`len` does not match the number of elements");
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// 1つのノードを1つの箱にして、初期化済みの要素と`len/N`を書く。
+impl<T: Debug, const N: usize> List<T, N> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", self.head as *const Node<T, N>, "");
+        dot.root("tail", self.tail as *const Node<T, N>, "");
+        let mut cur = self.head as *const Node<T, N>;
+        while !cur.is_null() {
+            let node = unsafe { &*cur };
+            dot.node(cur, &format!("{:?}\nlen={}/{}", node.as_slice(), node.len, N));
+            dot.edge(cur, node.next as *const Node<T, N>, "next");
+            dot.edge(cur, node.prev as *const Node<T, N>, "prev");
+            cur = node.next;
+        }
+        dot.finish()
+    }
+}
+
+impl<T, const N: usize> Default for List<T, N> {
+    fn default() -> Self {
+        List::new()
+    }
+}
+
+impl<T, const N: usize> IntoIterator for List<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self)
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a List<T, N> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, N>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, const N: usize> IntoIterator for &'a mut List<T, N> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T, N>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, const N: usize> Drop for List<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T, const N: usize> Extend<T> for List<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for elem in iter {
+            self.push_back(elem);
+        }
+    }
+}
+
+impl<T, const N: usize> ::std::iter::FromIterator<T> for List<T, N> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = List::new();
+        list.extend(iter);
+        list
+    }
+}
+
+// ノードは`List`だけが所有しているので、`Box`で持つ場合と同じ条件で送れる。
+unsafe impl<T: Send, const N: usize> Send for List<T, N> {}
+unsafe impl<T: Sync, const N: usize> Sync for List<T, N> {}
+
+impl<'a, T, const N: usize> Iterator for Iter<'a, T, N> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        unsafe {
+            if self.front_idx == (*self.front).len {
+                self.front = (*self.front).next;
+                self.front_idx = 0;
+            }
+            let elem = &*elem_ptr(self.front as *mut Node<T, N>, self.front_idx);
+            self.front_idx += 1;
+            Some(elem)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, const N: usize> DoubleEndedIterator for Iter<'a, T, N> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        unsafe {
+            if self.back_idx == 0 {
+                self.back = (*self.back).prev;
+                self.back_idx = (*self.back).len;
+            }
+            self.back_idx -= 1;
+            Some(&*elem_ptr(self.back as *mut Node<T, N>, self.back_idx))
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Iterator for IterMut<'a, T, N> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        unsafe {
+            if self.front_idx == (*self.front).len {
+                self.front = (*self.front).next;
+                self.front_idx = 0;
+            }
+            let elem = &mut *elem_ptr(self.front, self.front_idx);
+            self.front_idx += 1;
+            Some(elem)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, const N: usize> DoubleEndedIterator for IterMut<'a, T, N> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        unsafe {
+            if self.back_idx == 0 {
+                self.back = (*self.back).prev;
+                self.back_idx = (*self.back).len;
+            }
+            self.back_idx -= 1;
+            Some(&mut *elem_ptr(self.back, self.back_idx))
+        }
+    }
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T, const N: usize> DoubleEndedIterator for IntoIter<T, N> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::panic::{self, AssertUnwindSafe};
+    use super::List;
+    use node_alloc::test::PanicOnDrop;
+
+    fn node_lens<T, const N: usize>(list: &List<T, N>) -> Vec<usize> {
+        let mut lens = Vec::new();
+        let mut cur = list.head;
+        while !cur.is_null() {
+            let node = unsafe { &*cur };
+            lens.push(node.len);
+            cur = node.next;
+        }
+        lens
+    }
+
+    #[test]
+    fn basics() {
+        let mut list: List<i32, 4> = List::new();
+        assert_eq!(list.pop_front(), None);
+        assert_eq!(list.pop_back(), None);
+
+        for i in 0..6 {
+            list.push_back(i);
+        }
+        list.push_front(-1);
+        assert_eq!(list.len(), 7);
+        assert_eq!(node_lens(&list), vec![1, 4, 2]);
+        assert_eq!(list.front(), Some(&-1));
+        assert_eq!(list.back(), Some(&5));
+
+        assert_eq!(list.pop_front(), Some(-1));
+        assert_eq!(node_lens(&list), vec![4, 2]);
+        assert_eq!(list.pop_back(), Some(5));
+        assert_eq!(list.pop_back(), Some(4));
+        assert_eq!(node_lens(&list), vec![4]);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn indexing() {
+        let mut list: List<i32, 4> = (0..10).collect();
+        for i in 0..10 {
+            assert_eq!(list.get(i as usize), Some(&i));
+        }
+        assert_eq!(list.get(10), None);
+        *list.get_mut(7).unwrap() = 70;
+        assert_eq!(list.get(7), Some(&70));
+    }
+
+    #[test]
+    fn insert_splits_nodes() {
+        let mut list: List<i32, 4> = (0..4).collect();
+        assert_eq!(node_lens(&list), vec![4]);
+        list.insert(1, 10);
+        assert_eq!(node_lens(&list), vec![3, 2]);
+        list.insert(4, 20);
+        list.insert(6, 30);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 10, 1, 2, 20, 3, 30]);
+        assert_eq!(list.back(), Some(&30));
+    }
+
+    #[test]
+    fn remove_merges_nodes() {
+        let mut list: List<i32, 4> = (0..12).collect();
+        assert_eq!(node_lens(&list), vec![4, 4, 4]);
+        assert_eq!(list.remove(4), Some(4));
+        assert_eq!(list.remove(4), Some(5));
+        assert_eq!(list.remove(4), Some(6));
+        // 1つだけになったノードは、前後どちらと合わせても`N`を超えるのでそのまま。
+        assert_eq!(node_lens(&list), vec![4, 1, 4]);
+        assert_eq!(list.remove(0), Some(0));
+        assert_eq!(list.remove(0), Some(1));
+        assert_eq!(list.remove(0), Some(2));
+        assert_eq!(node_lens(&list), vec![2, 4]);
+        assert_eq!(list.remove(9), None);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![3, 7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn iterators() {
+        let mut list: List<i32, 3> = (0..8).collect();
+        assert_eq!(list.iter().rev().cloned().collect::<Vec<_>>(), vec![7, 6, 5, 4, 3, 2, 1, 0]);
+
+        let mut iter = list.iter();
+        assert_eq!(iter.next(), Some(&0));
+        assert_eq!(iter.next_back(), Some(&7));
+        assert_eq!(iter.size_hint(), (6, Some(6)));
+        assert_eq!(iter.cloned().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6]);
+
+        for v in &mut list {
+            *v *= 10;
+        }
+        let mut sum = 0;
+        for v in &list {
+            sum += *v;
+        }
+        assert_eq!(sum, 280);
+        let mut iter = list.iter_mut();
+        assert_eq!(iter.next_back(), Some(&mut 70));
+
+        let mut iter = list.into_iter();
+        assert_eq!(iter.next(), Some(0));
+        assert_eq!(iter.next_back(), Some(70));
+        assert_eq!(iter.collect::<Vec<_>>(), vec![10, 20, 30, 40, 50, 60]);
+    }
+
+    #[test]
+    fn matches_vec_deque() {
+        let mut list: List<u32, 5> = List::new();
+        let mut model = VecDeque::new();
+
+        let mut seed: u32 = 2024;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) & 0x7fff
+        };
+        for i in 0..20_000 {
+            let idx = rand() as usize % (model.len() + 1);
+            match rand() % 8 {
+                0 => { list.push_back(i); model.push_back(i); }
+                1 => { list.push_front(i); model.push_front(i); }
+                2 => assert_eq!(list.pop_back(), model.pop_back()),
+                3 => assert_eq!(list.pop_front(), model.pop_front()),
+                4 | 5 => { list.insert(idx, i); model.insert(idx, i); }
+                6 => assert_eq!(list.remove(idx), model.remove(idx)),
+                _ => assert_eq!(list.get(idx), model.get(idx)),
+            }
+            assert_eq!(list.len(), model.len());
+            assert!(node_lens(&list).iter().all(|&len| len > 0));
+        }
+        assert!(list.iter().eq(model.iter()));
+        assert!(list.iter().rev().eq(model.iter().rev()));
+    }
+
+    #[test]
+    fn drops_elements() {
+        use std::rc::Rc;
+        let rc = Rc::new(());
+        {
+            let mut list: List<Rc<()>, 4> = List::new();
+            for _ in 0..10 {
+                list.push_back(rc.clone());
+            }
+            list.remove(3);
+            list.pop_front();
+            assert_eq!(Rc::strong_count(&rc), 9);
+        }
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn clear_panic_safety() {
+        let drops: Vec<_> = (0..10).map(|_| Cell::new(0)).collect();
+        let extra = Cell::new(0);
+        let mut list: List<_, 4> = List::new();
+        for (i, d) in drops.iter().enumerate() {
+            list.push_back(PanicOnDrop(d, i == 1));
+        }
+        // 同じノードの残りの要素も、後ろのノードも drop される。
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.clear()));
+        assert!(result.is_err());
+        assert!(drops.iter().all(|d| d.get() == 1));
+
+        // `clear`が途中で panic しても、リストは空の状態で使い続けられる。
+        assert!(list.is_empty());
+        list.push_back(PanicOnDrop(&extra, false));
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list: List<i32, 4> = (0..10).collect();
+        list.debug_assert_invariants();
+
+        list.len += 1;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.len -= 1;
+
+        // 2つ目のノードの`prev`が自分自身を指している状態。
+        unsafe {
+            let second = (*list.head).next;
+            (*second).prev = second;
+            let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+            assert!(result.is_err());
+            (*second).prev = list.head;
+        }
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn to_dot() {
+        let list: List<i32, 2> = (0..3).collect();
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert!(out.contains("\"tail\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert_eq!(out.matches(" [label=\"prev\"]").count(), 1);
+        // ノードごとに要素の配列と、使っている数 / 容量が出る。
+        assert!(out.contains("\\n[0, 1]\\nlen=2/2\""));
+        assert!(out.contains("\\n[2]\\nlen=1/2\""));
+    }
+}
@@ -16,43 +16,198 @@
 // わずかに unsafe な操作を導入する事で、
 // RefCellを使う諸々の面倒さを避ける事はできている。
 
+use std::array;
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::mem;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use dot::Dot;
+use node_alloc::{AllocError, Global, NodeAllocator, NodeBox};
 
-type Link<T> = Option<Box<Node<T>>>;
+type Link<T, A> = Option<NodeBox<Node<T, A>, A>>;
 
-pub struct List<T> {
-    head: Link<T>,
-    tail: *mut Node<T>,
+// 位置を指定する操作で使う、手前のノードとそのノードを指している`Link`の組。
+type Position<T, A> = (*mut Node<T, A>, *mut Link<T, A>);
+
+// 手前のノード (先頭なら null) とそのノードの組。
+type NodeWithPrev<T, A> = (*mut Node<T, A>, *mut Node<T, A>);
+
+pub struct List<T, A: NodeAllocator = Global> {
+    head: Link<T, A>,
+    tail: *mut Node<T, A>,
+    slots: Slots<T, A>,
+    alloc: A,
 }
 
-pub struct IntoIter<T>(List<T>);
+pub struct IntoIter<T, A: NodeAllocator = Global>(List<T, A>);
 
-pub struct Iter<'a, T: 'a> {
-    next: Option<&'a Node<T>>,
+pub struct Iter<'a, T: 'a, A: NodeAllocator + 'a = Global> {
+    next: Option<&'a Node<T, A>>,
 }
 
-pub struct IterMut<'a, T: 'a> {
-    next: Option<&'a mut Node<T>>,
+pub struct IterMut<'a, T: 'a, A: NodeAllocator + 'a = Global> {
+    next: Option<&'a mut Node<T, A>>,
 }
 
-struct Node<T> {
+struct Node<T, A: NodeAllocator> {
     elem: T,
-    next: Link<T>,
+    next: Link<T, A>,
+    // ハンドルが付いていれば`Slots::entries`の添字、なければ`NO_SLOT`。
+    slot: usize,
+}
+
+// `push_with_handle`で入れた要素を、先頭から辿らずに O(1) で取り出すためのハンドル。
+// 中身はリストの番号と、`Slots`の添字とその世代だけなので、ハンドルが残っていても
+// ノードの解放は妨げない。要素がリストから取り除かれると (`pop`などで) 世代が進むので、
+// 古いハンドルや別のリストのハンドルは以降どの操作でも`None`になる。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    list: usize,
+    index: usize,
+    generation: u64,
+}
+
+// 片方向リストでノードを O(1) で取り除くには、その手前のノードがわかっていなければならない。
+// そこでハンドルの付いたノードについてだけ、ノードと手前のノードをこの表に記録しておく。
+// ノードの手前が変わる操作 (push、pop、remove、insert、ソートなど) は、
+// 後ろのノードにハンドルが付いていればここを更新する。
+struct Slots<T, A: NodeAllocator> {
+    // 別のリストのハンドルを見分けるための番号。
+    id: usize,
+    entries: Vec<Slot<T, A>>,
+    // 空いている`entries`の添字。
+    free: Vec<usize>,
+}
+
+struct Slot<T, A: NodeAllocator> {
+    // 空くたびに 1 進める。ハンドルの世代と違えば、そのハンドルは古い。
+    generation: u64,
+    // 空いていれば null。
+    node: *mut Node<T, A>,
+    // `node`の手前のノード (先頭なら null)。
+    prev: *mut Node<T, A>,
+}
+
+const NO_SLOT: usize = usize::MAX;
+
+static NEXT_LIST_ID: AtomicUsize = AtomicUsize::new(1);
+
+impl<T, A: NodeAllocator> Slots<T, A> {
+    fn new() -> Self {
+        Slots { id: NEXT_LIST_ID.fetch_add(1, AtomicOrdering::Relaxed), entries: Vec::new(), free: Vec::new() }
+    }
+
+    fn live(&self) -> usize {
+        self.entries.len() - self.free.len()
+    }
+
+    fn acquire(&mut self) -> usize {
+        match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.entries.push(Slot { generation: 0, node: ptr::null_mut(), prev: ptr::null_mut() });
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    // `slot`が`NO_SLOT`なら何もしない。
+    fn release(&mut self, slot: usize) {
+        if slot == NO_SLOT {
+            return;
+        }
+        let entry = &mut self.entries[slot];
+        entry.generation += 1;
+        entry.node = ptr::null_mut();
+        entry.prev = ptr::null_mut();
+        self.free.push(slot);
+    }
+
+    // ハンドルの付いたノードはすべて解放済み (ソート中に panic した場合など) なので、ノードには触らない。
+    fn release_all(&mut self) {
+        for slot in 0..self.entries.len() {
+            if !self.entries[slot].node.is_null() {
+                self.release(slot);
+            }
+        }
+    }
+
+    // 生きているハンドルの指すノードから番号を外し、すべて古いハンドルにする。
+    // ノードを別のリストへ移す前に使う。
+    unsafe fn detach_all(&mut self) {
+        for entry in &self.entries {
+            if !entry.node.is_null() {
+                (*entry.node).slot = NO_SLOT;
+            }
+        }
+        self.release_all();
+    }
+
+    // `node`にハンドルが付いていれば、記録を`node`と`prev`で置き換える。
+    unsafe fn track(&mut self, node: *mut Node<T, A>, prev: *mut Node<T, A>) {
+        let slot = (*node).slot;
+        if slot != NO_SLOT {
+            self.entries[slot].node = node;
+            self.entries[slot].prev = prev;
+        }
+    }
+
+    // 生きているハンドルなら、その手前のノードとノードを返す。
+    fn find(&self, handle: Handle) -> Option<NodeWithPrev<T, A>> {
+        if handle.list != self.id {
+            return None;
+        }
+        let entry = self.entries.get(handle.index)?;
+        if entry.generation != handle.generation || entry.node.is_null() {
+            return None;
+        }
+        Some((entry.prev, entry.node))
+    }
 }
 
 impl<T> List<T> {
     pub fn new() -> Self {
+        List::new_in(Global)
+    }
+}
+
+impl<T, A: NodeAllocator + Clone> List<T, A> {
+    pub fn new_in(alloc: A) -> Self {
         // *mut な raw pointer は nullable なので、Optionを使う意味がない。
         // null を None 代わりに使う。ただし Java などの null とは違い、
         // null も各種メソッドを持った primitve type (raw pointer) となる。
-        List { head: None, tail: ptr::null_mut() }
+        List { head: None, tail: ptr::null_mut(), slots: Slots::new(), alloc }
     }
 
     pub fn push(&mut self, elem: T) {
-        let mut new_tail = Box::new(Node { elem, next: None, });
+        let new_tail = NodeBox::new_in(Node { elem, next: None, slot: NO_SLOT }, self.alloc.clone());
+        self.push_node(new_tail);
+    }
+
+    // 確保に失敗した場合は abort せず、`elem`をそのまま返す。リストは変わらない。
+    pub fn try_push(&mut self, elem: T) -> Result<(), (AllocError, T)> {
+        match NodeBox::try_new_in(Node { elem, next: None, slot: NO_SLOT }, self.alloc.clone()) {
+            Ok(new_tail) => {
+                self.push_node(new_tail);
+                Ok(())
+            }
+            Err((e, node)) => Err((e, node.elem)),
+        }
+    }
+
+    // 末尾にハンドル付きで追加する。ハンドルは要素がリストから取り除かれるまで使える。
+    pub fn push_with_handle(&mut self, elem: T) -> Handle {
+        let index = self.slots.acquire();
+        let new_tail = NodeBox::new_in(Node { elem, next: None, slot: index }, self.alloc.clone());
+        self.push_node(new_tail);
+        Handle { list: self.slots.id, index, generation: self.slots.entries[index].generation }
+    }
 
+    fn push_node(&mut self, mut new_tail: NodeBox<Node<T, A>, A>) {
         // 通常の値を raw pointer にするには、 raw pointer型として deref する。
         let raw_tail: *mut _ = &mut *new_tail;
+        unsafe { self.slots.track(raw_tail, self.tail) };
 
         if self.tail.is_null() {
             self.head = Some(new_tail);
@@ -70,77 +225,436 @@ impl<T> List<T> {
         }
 
         self.tail = raw_tail;
+        self.validate();
     }
+}
 
+impl<T, A: NodeAllocator> List<T, A> {
     pub fn pop(&mut self) -> Option<T> {
-        self.head.take().map(|node| {
-            let node = *node;
+        let elem = self.head.take().map(|node| {
+            let node = NodeBox::into_inner(node);
             self.head = node.next;
 
-            if self.head.is_none() {
-                // もしこの null 化処理を忘れたら、次の push はおかしな場所にノードを
-                // 挿入することになる。しかしコンパイル時にはそれがわからない。
-                self.tail = ptr::null_mut();
+            match self.head {
+                Some(ref mut head) => unsafe { self.slots.track(&mut **head, ptr::null_mut()) },
+                None => {
+                    // もしこの null 化処理を忘れたら、次の push はおかしな場所にノードを
+                    // 挿入することになる。しかしコンパイル時にはそれがわからない。
+                    self.tail = ptr::null_mut();
+                }
             }
 
+            self.slots.release(node.slot);
             node.elem
-        })
+        });
+        self.validate();
+        elem
     }
 
-    pub fn into_iter(self) -> IntoIter<T> {
+    pub fn into_iter(self) -> IntoIter<T, A> {
         IntoIter(self)
     }
 
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<T, A> {
         Iter { next: self.head.as_ref().map(|node| &**node) }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<T, A> {
         IterMut { next: self.head.as_mut().map(|node| &mut **node) }
     }
 }
 
-impl<T> Drop for List<T> {
+// 先頭から辿って位置を指定する操作。どれも`i`番目まで辿るので O(i)。
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn front(&self) -> Option<&T> {
+        self.head.as_ref().map(|node| &node.elem)
+    }
+
+    // `tail`は末尾のノードを指しているので、辿らずに O(1) で見られる。
+    pub fn back(&self) -> Option<&T> {
+        unsafe { self.tail.as_ref().map(|node| &node.elem) }
+    }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        self.iter().nth(i)
+    }
+
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut T> {
+        self.iter_mut().nth(i)
+    }
+
+    pub fn contains(&self, elem: &T) -> bool where T: PartialEq {
+        self.iter().any(|e| e == elem)
+    }
+
+    // `i`番目の要素を取り除く。`i`が範囲外なら`None`。
+    pub fn remove(&mut self, i: usize) -> Option<T> {
+        let (prev, link) = self.link_at(i)?;
+        let elem = unsafe { self.unlink(prev, link)? };
+        self.validate();
+        Some(elem)
+    }
+
+    // `i`番目と`j`番目の要素を入れ替える。ノードはそのままで、要素だけを動かす。
+    // ハンドルは要素についていくので、ノードの番号も一緒に入れ替える。
+    // どちらかが範囲外なら panic する (`slice::swap`と同じ)。
+    pub fn swap(&mut self, i: usize, j: usize) {
+        let (prev_a, a) = self.node_at(i).expect("index out of bounds");
+        let (prev_b, b) = self.node_at(j).expect("index out of bounds");
+        unsafe {
+            ptr::swap(ptr::addr_of_mut!((*a).elem), ptr::addr_of_mut!((*b).elem));
+            ptr::swap(ptr::addr_of_mut!((*a).slot), ptr::addr_of_mut!((*b).slot));
+            self.slots.track(a, prev_a);
+            self.slots.track(b, prev_b);
+        }
+        self.validate();
+    }
+
+    // `link`が指しているノードを外して要素を返す。`prev`はそのノードの手前 (先頭なら null)。
+    unsafe fn unlink(&mut self, prev: *mut Node<T, A>, link: *mut Link<T, A>) -> Option<T> {
+        let node = NodeBox::into_inner((&mut *link).take()?);
+        *link = node.next;
+        match *link {
+            Some(ref mut next) => self.slots.track(&mut **next, prev),
+            // 末尾のノードを取り除いたなら、その手前 (先頭だったなら null) が新しい末尾。
+            None => self.tail = prev,
+        }
+        self.slots.release(node.slot);
+        Some(node.elem)
+    }
+
+    // `i`番目のノードを指している`Link` (先頭なら`head`、それ以外は手前のノードの`next`) と、
+    // その手前のノード (先頭なら null) を返す。`i`が長さを超えていれば`None`。
+    // `i`が長さと等しい場合は、末尾のノードの`next` (つまり`None`) を返す。
+    fn link_at(&mut self, i: usize) -> Option<Position<T, A>> {
+        let mut prev: *mut Node<T, A> = ptr::null_mut();
+        let mut link: *mut Link<T, A> = &mut self.head;
+        for _ in 0..i {
+            unsafe {
+                prev = match *link {
+                    Some(ref mut node) => &mut **node,
+                    None => return None,
+                };
+                link = &mut (*prev).next;
+            }
+        }
+        Some((prev, link))
+    }
+
+    fn node_at(&mut self, i: usize) -> Option<NodeWithPrev<T, A>> {
+        let (prev, link) = self.link_at(i)?;
+        unsafe {
+            match *link {
+                Some(ref mut node) => Some((prev, &mut **node)),
+                None => None,
+            }
+        }
+    }
+}
+
+// ハンドルを使って、先頭から辿らずに O(1) で操作する。
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn get_by_handle(&self, handle: Handle) -> Option<&T> {
+        let (_, node) = self.slots.find(handle)?;
+        unsafe { Some(&(*node).elem) }
+    }
+
+    pub fn get_mut_by_handle(&mut self, handle: Handle) -> Option<&mut T> {
+        let (_, node) = self.slots.find(handle)?;
+        unsafe { Some(&mut (*node).elem) }
+    }
+
+    pub fn remove_by_handle(&mut self, handle: Handle) -> Option<T> {
+        let (prev, _) = self.slots.find(handle)?;
+        let elem = unsafe {
+            let link: *mut Link<T, A> = if prev.is_null() { &mut self.head } else { &mut (*prev).next };
+            self.unlink(prev, link)
+        };
+        self.validate();
+        elem
+    }
+}
+
+impl<T, A: NodeAllocator + Clone> List<T, A> {
+    // `i`番目に挿入し、それ以降の要素を1つずつ後ろにずらす。
+    // `i`が長さを超えていれば panic する (`Vec::insert`と同じ)。
+    pub fn insert(&mut self, i: usize, elem: T) {
+        let (_, link) = self.link_at(i).expect("index out of bounds");
+        let mut new_node = NodeBox::new_in(Node { elem, next: None, slot: NO_SLOT }, self.alloc.clone());
+        unsafe {
+            new_node.next = (&mut *link).take();
+            let raw: *mut _ = &mut *new_node;
+            match (*raw).next {
+                Some(ref mut next) => self.slots.track(&mut **next, raw),
+                None => self.tail = raw,
+            }
+            *link = Some(new_node);
+        }
+        self.validate();
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// ノードは`head`から`next`で所有されているので循環はありえず、確かめるのは`tail`とハンドルの表だけでよい。
+// `tail`は空なら null、そうでなければ`head`から辿った最後のノードを指していなければならない。
+// `pop`で null に戻し忘れると、ここで解放済みのノードを指している事がわかる。
+// ハンドルの付いたノードについては、表の記録がそのノードと実際の手前のノードに一致している事を確かめる。
+// `validate`フィーチャーを有効にすると、リストを変更する操作のたびに呼ばれる。
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn debug_assert_invariants(&self) {
+        let mut last: *const Node<T, A> = ptr::null();
+        let mut tracked = 0;
+        let mut link = &self.head;
+        while let Some(ref node) = *link {
+            if node.slot != NO_SLOT {
+                let entry = &self.slots.entries[node.slot];
+                assert!(ptr::eq(entry.node, &**node), "slot {} does not point to its node {:p}", node.slot, &**node);
+                assert!(ptr::eq(entry.prev, last), "slot {} records {:p} as `prev`, but it is {:p}", node.slot, entry.prev, last);
+                tracked += 1;
+            }
+            last = &**node;
+            link = &node.next;
+        }
+        assert!(ptr::eq(self.tail, last), "`tail` is {:p}, but the last node is {:p}", self.tail, last);
+        assert_eq!(self.slots.live(), tracked, "live slots do not match the nodes with handles");
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// `tail`は辿らずにアドレスをそのまま書くので、`pop`で null に戻し忘れたような
+// 壊れた状態でも、どこを指しているかがわかる。
+impl<T: Debug, A: NodeAllocator> List<T, A> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        self.write_dot(&mut dot, "head", "tail");
+        dot.finish()
+    }
+
+    // `priority_queue`からも使う。根の名前を変えれば、複数のリストを1つの図に並べられる。
+    pub(crate) fn write_dot(&self, dot: &mut Dot, head: &str, tail: &str) {
+        dot.root(head, node_ptr(&self.head), "");
+        dot.root(tail, self.tail as *const Node<T, A>, "");
+        let mut link = &self.head;
+        while let Some(ref node) = *link {
+            dot.node(&**node as *const Node<T, A>, &format!("{:?}", node.elem));
+            dot.edge(&**node as *const Node<T, A>, node_ptr(&node.next), "next");
+            link = &node.next;
+        }
+    }
+}
+
+fn node_ptr<T, A: NodeAllocator>(link: &Link<T, A>) -> *const Node<T, A> {
+    link.as_ref().map_or(ptr::null(), |node| &**node)
+}
+
+impl<T, A: NodeAllocator> Drop for List<T, A> {
     fn drop(&mut self) {
-        let mut cur_link = self.head.take();
-        while let Some(mut boxed_node) = cur_link {
-            cur_link = boxed_node.next.take();
+        // 要素の drop が panic しても、残りの要素は`DropGuard`が drop し続ける。
+        // (その途中でさらに panic すると abort になる。)
+        struct DropGuard<'a, T: 'a, A: NodeAllocator + 'a>(&'a mut List<T, A>);
+        impl<'a, T, A: NodeAllocator> Drop for DropGuard<'a, T, A> {
+            fn drop(&mut self) {
+                while self.0.pop().is_some() {}
+            }
         }
+        let guard = DropGuard(self);
+        while guard.0.pop().is_some() {}
     }
 }
 
-impl<T> Iterator for IntoIter<T> {
+// `tail`は`head`から辿れるノードを指しているだけで、ノードはすべて`head`側が所有している。
+// 生ポインタがあるので自動では実装されないが、`NodeBox`と同じ条件で送れる。
+unsafe impl<T: Send, A: NodeAllocator + Send> Send for List<T, A> {}
+unsafe impl<T: Sync, A: NodeAllocator + Sync> Sync for List<T, A> {}
+
+impl<T, A: NodeAllocator> Iterator for IntoIter<T, A> {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
         self.0.pop()
     }
 }
 
-impl<'a, T> Iterator for Iter<'a, T> {
+impl<'a, T, A: NodeAllocator> Iterator for Iter<'a, T, A> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
         self.next.map(|node| {
-            self.next = node.next.as_ref().map(|n| &**n);
+            self.next = node.next.as_deref();
             &node.elem
         })
     }
 }
 
-impl<'a, T> Iterator for IterMut<'a, T> {
+impl<'a, T, A: NodeAllocator> Iterator for IterMut<'a, T, A> {
     type Item = &'a mut T;
     fn next(&mut self) -> Option<Self::Item> {
         // XXX: なぜ &mut の場合だけ`take`が必要なのかわからない。。
         // 直接`map`すると`cannot move out of borrowed content`になる。
         self.next.take().map(|node| {
-            self.next = node.next.as_mut().map(|n| &mut **n);
+            self.next = node.next.as_deref_mut();
             &mut node.elem
         })
     }
 }
 
+// ソート
+
+impl<T, A: NodeAllocator> List<T, A> {
+    pub fn sort(&mut self) where T: Ord {
+        self.sort_by(|a, b| a.cmp(b));
+    }
+
+    pub fn sort_by_key<K: Ord, F: FnMut(&T) -> K>(&mut self, mut f: F) {
+        self.sort_by(|a, b| f(a).cmp(&f(b)));
+    }
+
+    // `compare`が panic した場合、要素はすべて drop されてリストは空になる。
+    // ソート中のノードは`self`の外にあるので、先に`tail`を null にしておかないと、
+    // panic のあとに解放済みのノードを指したままになってしまう。
+    // ハンドルの表も同じで、panic したら`ReleaseSlots`がすべて古いハンドルにする。
+    pub fn sort_by<F: FnMut(&T, &T) -> Ordering>(&mut self, mut compare: F) {
+        let head = self.head.take();
+        self.tail = ptr::null_mut();
+        let guard = ReleaseSlots(&mut self.slots);
+        self.head = merge_sort(head, &mut compare);
+        mem::forget(guard);
+        self.reset_tail();
+        self.validate();
+    }
+
+    // 自身と`other`がどちらもソート済みである事を前提に、`other`の要素を取り込む。
+    pub fn merge(&mut self, other: List<T, A>) where T: Ord {
+        self.merge_by(other, |a, b| a.cmp(b));
+    }
+
+    pub fn merge_by<F: FnMut(&T, &T) -> Ordering>(&mut self, mut other: List<T, A>, mut compare: F) {
+        let head = self.head.take();
+        self.tail = ptr::null_mut();
+        // `other`のハンドルの番号は`self`の表では意味を持たないので、先に外しておく。
+        unsafe { other.slots.detach_all() };
+        let other_head = other.head.take();
+        other.tail = ptr::null_mut();
+        let guard = ReleaseSlots(&mut self.slots);
+        self.head = merge(head, other_head, &mut compare);
+        mem::forget(guard);
+        self.reset_tail();
+        self.validate();
+    }
+}
+
+struct ReleaseSlots<'a, T: 'a, A: NodeAllocator + 'a>(&'a mut Slots<T, A>);
+
+impl<'a, T, A: NodeAllocator> Drop for ReleaseSlots<'a, T, A> {
+    fn drop(&mut self) {
+        self.0.release_all();
+    }
+}
+
+impl<T, A: NodeAllocator> List<T, A> {
+    // ノードを繋ぎ変えたあとは`tail`がどこを指しているかわからないので、
+    // 末尾まで辿って付け直す。ついでにハンドルの表の手前のノードも付け直す。
+    fn reset_tail(&mut self) {
+        let mut tail: *mut Node<T, A> = ptr::null_mut();
+        let mut cur: *mut Node<T, A> = match self.head {
+            Some(ref mut node) => &mut **node,
+            None => ptr::null_mut(),
+        };
+        while !cur.is_null() {
+            unsafe { self.slots.track(cur, tail) };
+            tail = cur;
+            cur = unsafe {
+                match (*cur).next {
+                    Some(ref mut node) => &mut **node,
+                    None => ptr::null_mut(),
+                }
+            };
+        }
+        self.tail = tail;
+    }
+}
+
+// 連結リストのソートはノードを繋ぎ変えるだけで済むので、要素の移動も追加の確保も要らない。
+// 再帰で分割するとリストが長いときに stack overflow するので、
+// 長さ 2^i のソート済みの連を`bins[i]`に貯めていくボトムアップのマージソートにする。
+// `bins`は高々64個なので、必要なメモリはリストの長さによらず一定。
+fn merge_sort<T, A, F>(list: Link<T, A>, compare: &mut F) -> Link<T, A>
+    where A: NodeAllocator, F: FnMut(&T, &T) -> Ordering
+{
+    let mut list = Run(list);
+    let mut bins: [Run<T, A>; 64] = array::from_fn(|_| Run(None));
+    while let Some(mut node) = list.0.take() {
+        list.0 = node.next.take();
+        let mut run = Run(Some(node));
+        let mut i = 0;
+        // 添字が大きい`bins`ほど元のリストで前にあった要素を持つ。
+        // 安定ソートにするため、常に前にあった側を`merge`の第1引数にする。
+        while let Some(bin) = bins[i].0.take() {
+            run = Run(merge(Some(bin), run.0.take(), compare));
+            i += 1;
+        }
+        bins[i] = run;
+    }
+
+    let mut sorted = Run(None);
+    for bin in bins.iter_mut() {
+        if bin.0.is_some() {
+            sorted = Run(merge(bin.0.take(), sorted.0.take(), compare));
+        }
+    }
+    sorted.0.take()
+}
+
+// ソート済みの`a`と`b`を1つにまとめる。等しい要素は`a`のものが先になる。
+fn merge<T, A, F>(a: Link<T, A>, b: Link<T, A>, compare: &mut F) -> Link<T, A>
+    where A: NodeAllocator, F: FnMut(&T, &T) -> Ordering
+{
+    let (mut a, mut b) = (Run(a), Run(b));
+    let mut head = Run(None);
+    {
+        let mut tail = &mut head.0;
+        while let (Some(node_a), Some(node_b)) = (a.0.as_ref(), b.0.as_ref()) {
+            let src = if compare(&node_b.elem, &node_a.elem) == Ordering::Less {
+                &mut b.0
+            } else {
+                &mut a.0
+            };
+            let mut node = src.take().unwrap();
+            *src = node.next.take();
+            tail = &mut tail.insert(node).next;
+        }
+        *tail = if a.0.is_some() { a.0.take() } else { b.0.take() };
+    }
+    head.0.take()
+}
+
+// ソートの途中の連。`compare`が panic すると連はそのまま drop されるが、
+// `Link`の drop は`next`を辿って再帰するので、長い連だと stack overflow する。
+// そこで`List::drop`と同じように、ノードを1つずつ外してから drop する。
+struct Run<T, A: NodeAllocator>(Link<T, A>);
+
+impl<T, A: NodeAllocator> Drop for Run<T, A> {
+    fn drop(&mut self) {
+        let mut link = self.0.take();
+        while let Some(mut node) = link {
+            link = node.next.take();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::panic::{self, AssertUnwindSafe};
     use super::List;
+    use node_alloc::AllocError;
+    use node_alloc::test::{CountingAlloc, PanicOnDrop};
 
     #[test]
     fn basics() {
@@ -194,4 +708,379 @@ mod test {
         assert_eq!(iter.next(), Some(&mut 1));
         assert_eq!(iter.next(), Some(&mut 2));
     }
+
+    #[test]
+    fn custom_allocator() {
+        let alloc = CountingAlloc::default();
+        {
+            let mut list = List::new_in(&alloc);
+            list.push(1); list.push(2); list.push(3);
+            assert_eq!(alloc.live(), 3);
+
+            assert_eq!(list.pop(), Some(1));
+            assert_eq!(alloc.live(), 2);
+
+            let mut iter = list.into_iter();
+            assert_eq!(iter.next(), Some(2));
+            assert_eq!(alloc.live(), 1);
+        }
+        assert_eq!(alloc.allocs.get(), 3);
+        assert_eq!(alloc.live(), 0);
+    }
+
+    #[test]
+    fn try_push() {
+        let alloc = CountingAlloc::default();
+        alloc.limit.set(Some(2));
+        let mut list = List::new_in(&alloc);
+        assert!(list.try_push(1).is_ok());
+        assert!(list.try_push(2).is_ok());
+
+        match list.try_push(3) {
+            Err((AllocError, elem)) => assert_eq!(elem, 3),
+            Ok(()) => panic!("allocation should fail"),
+        }
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&1, &2]);
+        assert_eq!(alloc.live(), 2);
+
+        // 失敗しても`tail`は壊れていないので、続けて末尾に追加できる。
+        alloc.limit.set(None);
+        assert!(list.try_push(3).is_ok());
+        assert_eq!(list.into_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn sort() {
+        let mut list = List::new();
+        list.sort();
+        assert_eq!(list.pop(), None);
+
+        for &v in &[3, 1, 4, 1, 5, 9, 2, 6, 5, 3] {
+            list.push(v);
+        }
+        list.sort();
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![1, 1, 2, 3, 3, 4, 5, 5, 6, 9]);
+
+        // `tail`が付け直されていれば、push した要素は末尾に入る。
+        list.push(0);
+        list.sort_by(|a, b| b.cmp(a));
+        list.push(10);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![9, 6, 5, 5, 4, 3, 3, 2, 1, 1, 0, 10]);
+    }
+
+    #[test]
+    fn sort_is_stable() {
+        let mut list = List::new();
+        for &v in &[(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (0, 'e')] {
+            list.push(v);
+        }
+        list.sort_by_key(|&(k, _)| k);
+        assert_eq!(list.iter().map(|&(_, c)| c).collect::<String>(), "ebdac");
+    }
+
+    // push のたびにリスト全体を辿る事になるので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn sort_long_list() {
+        let mut list = List::new();
+        let mut x: u32 = 1;
+        for _ in 0..1_000_000 {
+            x ^= x << 13; x ^= x >> 17; x ^= x << 5;
+            list.push(x);
+        }
+        list.sort();
+        let mut iter = list.iter();
+        let mut prev = iter.next().unwrap();
+        let mut count = 1;
+        for v in iter {
+            assert!(prev <= v);
+            prev = v;
+            count += 1;
+        }
+        assert_eq!(count, 1_000_000);
+        list.push(0);
+        assert_eq!(list.iter().last(), Some(&0));
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = List::new();
+        for &v in &[(1, 'a'), (3, 'a'), (7, 'a')] {
+            a.push(v);
+        }
+        let mut b = List::new();
+        for &v in &[(1, 'b'), (2, 'b'), (3, 'b'), (8, 'b')] {
+            b.push(v);
+        }
+        a.merge_by(b, |x, y| x.0.cmp(&y.0));
+        a.push((9, 'a'));
+        assert_eq!(
+            a.iter().cloned().collect::<Vec<_>>(),
+            vec![(1, 'a'), (1, 'b'), (2, 'b'), (3, 'a'), (3, 'b'), (7, 'a'), (8, 'b'), (9, 'a')]
+        );
+
+        let mut c = List::new();
+        c.merge(List::new());
+        c.push(3);
+        let mut d = List::new();
+        d.push(1); d.push(2);
+        c.merge(d);
+        c.push(4);
+        assert_eq!(c.into_iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn drop_panic_safety() {
+        let drops: Vec<_> = (0..5).map(|_| Cell::new(0)).collect();
+        let mut list = List::new();
+        for (i, d) in drops.iter().enumerate() {
+            list.push(PanicOnDrop(d, i == 2));
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(move || drop(list)));
+        assert!(result.is_err());
+        // panic した要素のあとも drop は続き、どの要素も1回だけ drop される。
+        assert!(drops.iter().all(|d| d.get() == 1));
+    }
+
+    #[test]
+    fn sort_panic_safety() {
+        let drops: Vec<_> = (0..5).map(|_| Cell::new(0)).collect();
+        let mut list = List::new();
+        for d in &drops {
+            list.push(PanicOnDrop(d, false));
+        }
+        let mut calls = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            list.sort_by(|_, _| {
+                calls += 1;
+                if calls == 3 {
+                    panic!("compare panicked");
+                }
+                Ordering::Less
+            })
+        }));
+        assert!(result.is_err());
+        assert!(drops.iter().all(|d| d.get() == 1));
+
+        // リストは空になるが、`tail`が解放済みのノードを指したままにはならない。
+        assert!(list.pop().is_none());
+        let extra = Cell::new(0);
+        list.push(PanicOnDrop(&extra, false));
+        assert_eq!(list.iter().count(), 1);
+        drop(list);
+        assert_eq!(extra.get(), 1);
+    }
+
+    // 長い連を持っている途中で`compare`が panic しても、stack overflow せずにすべて drop される。
+    // push のたびにリスト全体を辿る事になるので、`validate`では飛ばす。
+    #[test]
+    #[cfg_attr(feature = "validate", ignore)]
+    fn sort_panic_long_list() {
+        let drops = Cell::new(0);
+        let mut list = List::new();
+        let mut x: u32 = 1;
+        for _ in 0..1_000_000 {
+            x ^= x << 13; x ^= x >> 17; x ^= x << 5;
+            list.push((x, PanicOnDrop(&drops, false)));
+        }
+        let mut calls = 0;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            list.sort_by(|a, b| {
+                calls += 1;
+                if calls == 15_000_000 {
+                    panic!("compare panicked");
+                }
+                a.0.cmp(&b.0)
+            });
+        }));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 1_000_000);
+        assert!(list.pop().is_none());
+        list.push((0, PanicOnDrop(&drops, false)));
+        assert_eq!(list.iter().count(), 1);
+    }
+
+    #[test]
+    fn peek() {
+        let mut list = List::new();
+        assert_eq!(list.front(), None);
+        assert_eq!(list.back(), None);
+        list.push(1);
+        list.push(2);
+        assert_eq!(list.front(), Some(&1));
+        assert_eq!(list.back(), Some(&2));
+        list.pop();
+        assert_eq!(list.front(), Some(&2));
+        assert_eq!(list.back(), Some(&2));
+    }
+
+    #[test]
+    fn indexing() {
+        let mut list = List::new();
+        list.push(0); list.push(1); list.push(2);
+        assert_eq!(list.get(0), Some(&0));
+        assert_eq!(list.get(2), Some(&2));
+        assert_eq!(list.get(3), None);
+        *list.get_mut(1).unwrap() = 10;
+        assert!(list.contains(&10));
+        assert!(!list.contains(&1));
+
+        list.swap(0, 2);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![2, 10, 0]);
+        list.swap(1, 1);
+        assert_eq!(list.back(), Some(&0));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut list = List::new();
+        list.insert(0, 1);
+        list.insert(0, 0);
+        list.insert(2, 3);
+        list.insert(2, 2);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(list.back(), Some(&3));
+
+        // 末尾に挿入したあとの push はさらにその後ろに繋がる。
+        list.push(4);
+        assert_eq!(list.back(), Some(&4));
+
+        assert_eq!(list.remove(5), None);
+        assert_eq!(list.remove(2), Some(2));
+        assert_eq!(list.remove(3), Some(4));
+        assert_eq!(list.back(), Some(&3));
+        list.push(5);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 1, 3, 5]);
+
+        assert_eq!(list.remove(0), Some(0));
+        assert_eq!(list.remove(0), Some(1));
+        assert_eq!(list.remove(1), Some(5));
+        assert_eq!(list.remove(0), Some(3));
+        assert_eq!(list.back(), None);
+        list.push(6);
+        assert_eq!(list.front(), Some(&6));
+        assert_eq!(list.back(), Some(&6));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_bounds() {
+        let mut list = List::new();
+        list.push(0);
+        list.insert(2, 1);
+    }
+
+    #[test]
+    fn handles() {
+        let mut list = List::new();
+        let a = list.push_with_handle('a');
+        list.push('b');
+        let c = list.push_with_handle('c');
+        let d = list.push_with_handle('d');
+        assert_eq!(list.get_by_handle(c), Some(&'c'));
+
+        *list.get_mut_by_handle(a).unwrap() = 'A';
+        assert_eq!(list.front(), Some(&'A'));
+
+        // 真ん中、末尾、先頭の順に取り除く。
+        assert_eq!(list.remove_by_handle(c), Some('c'));
+        assert_eq!(list.remove_by_handle(d), Some('d'));
+        assert_eq!(list.back(), Some(&'b'));
+        list.push('e');
+        assert_eq!(list.remove_by_handle(a), Some('A'));
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec!['b', 'e']);
+
+        // 取り除いたあとのハンドルはどれも使えない。同じ番号が再利用されても世代で見分ける。
+        let f = list.push_with_handle('f');
+        assert_eq!(list.get_by_handle(a), None);
+        assert_eq!(list.get_mut_by_handle(c), None);
+        assert_eq!(list.remove_by_handle(d), None);
+        assert_eq!(list.get_by_handle(f), Some(&'f'));
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn stale_handles() {
+        let mut list = List::new();
+        let a = list.push_with_handle(1);
+        let b = list.push_with_handle(2);
+        let c = list.push_with_handle(3);
+        assert_eq!(list.pop(), Some(1));
+        assert_eq!(list.remove(1), Some(3));
+        assert_eq!(list.get_by_handle(a), None);
+        assert_eq!(list.get_by_handle(c), None);
+        assert_eq!(list.remove_by_handle(b), Some(2));
+        assert_eq!(list.remove_by_handle(b), None);
+
+        // 別のリストのハンドルも使えない。
+        let mut other = List::new();
+        let x = other.push_with_handle(1);
+        list.push(1);
+        assert_eq!(list.get_by_handle(x), None);
+        assert_eq!(list.remove_by_handle(x), None);
+        assert_eq!(other.get_by_handle(x), Some(&1));
+    }
+
+    #[test]
+    fn handles_follow_elements() {
+        let mut list = List::new();
+        let three = list.push_with_handle(3);
+        list.push(1);
+        let two = list.push_with_handle(2);
+        list.insert(0, 0);
+
+        list.swap(1, 3);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 2, 1, 3]);
+        assert_eq!(list.get_by_handle(three), Some(&3));
+        list.debug_assert_invariants();
+
+        list.sort();
+        assert_eq!(list.remove_by_handle(two), Some(2));
+        assert_eq!(list.remove_by_handle(three), Some(3));
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 1]);
+        list.debug_assert_invariants();
+
+        // マージで移ってきたノードのハンドルは古くなる。
+        let mut other = List::new();
+        let moved = other.push_with_handle(5);
+        let one = list.push_with_handle(4);
+        list.merge(other);
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![0, 1, 4, 5]);
+        assert_eq!(list.get_by_handle(moved), None);
+        assert_eq!(list.remove_by_handle(one), Some(4));
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list = List::new();
+        list.debug_assert_invariants();
+        list.push(1);
+        list.push(2);
+        list.debug_assert_invariants();
+
+        // 末尾ではないノードを`tail`が指している状態。
+        let tail = list.tail;
+        list.tail = &mut **list.head.as_mut().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.tail = tail;
+
+        assert_eq!(list.pop(), Some(1));
+        assert_eq!(list.pop(), Some(2));
+        list.debug_assert_invariants();
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut list = List::new();
+        list.push(1);
+        list.push(2);
+        let out = list.to_dot();
+        assert!(out.contains("\"head\" -> "));
+        assert!(out.contains("\"tail\" -> "));
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+        assert!(out.contains("\\n1\"]"));
+        assert!(out.contains("\\n2\"]"));
+    }
 }
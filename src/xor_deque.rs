@@ -0,0 +1,512 @@
+// XOR linked list (実験的)。
+// 各ノードは`prev`と`next`の代わりに、両者のアドレスの XOR だけを持つ。
+// 片方の隣のアドレスがわかっていれば、もう片方は`link ^ 隣のアドレス`で求まる。
+// 端から辿る限りは常に隣がわかっているので、1ワードで双方向に辿れる。
+
+// 整数にしたアドレスからポインタへ戻すので、普通の`as`キャストでは
+// ポインタの provenance (どの確保に由来するか) が失われてしまう。
+// そこで exposed provenance の API を使い、ノードのアドレスを XOR に混ぜる前に
+// `expose_provenance`で公開しておき、戻すときは`with_exposed_provenance_mut`を使う。
+// こうしておけば、整数から戻したポインタもそのノードの確保に由来するものとして扱われる。
+
+// 実験的なモジュールなので、インターフェースは予告なく変わりうる。
+// そのため`lib.rs`では`#[doc(hidden)]`にして、ドキュメントには載せていない。
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use dot::Dot;
+
+pub struct List<T> {
+    head: *mut Node<T>,
+    tail: *mut Node<T>,
+    len: usize,
+    _marker: PhantomData<Box<Node<T>>>,
+}
+
+struct Node<T> {
+    elem: T,
+    // 前後のノードのアドレスの XOR。端では存在しない側を 0 (null) として扱う。
+    link: usize,
+}
+
+pub struct Iter<'a, T: 'a> {
+    front: *const Node<T>,
+    front_prev: usize,
+    back: *const Node<T>,
+    back_next: usize,
+    len: usize,
+    _marker: PhantomData<&'a T>,
+}
+
+pub struct IterMut<'a, T: 'a> {
+    front: *mut Node<T>,
+    front_prev: usize,
+    back: *mut Node<T>,
+    back_next: usize,
+    len: usize,
+    _marker: PhantomData<&'a mut T>,
+}
+
+pub struct IntoIter<T>(List<T>);
+
+fn addr<T>(node: *mut Node<T>) -> usize {
+    node.expose_provenance()
+}
+
+fn from_addr<T>(addr: usize) -> *mut Node<T> {
+    ptr::with_exposed_provenance_mut(addr)
+}
+
+// `node`の隣の一方が`neighbor`のとき、もう一方の隣を返す。
+unsafe fn other<T>(node: *const Node<T>, neighbor: usize) -> *mut Node<T> {
+    from_addr((*node).link ^ neighbor)
+}
+
+impl<T> List<T> {
+    pub fn new() -> Self {
+        List { head: ptr::null_mut(), tail: ptr::null_mut(), len: 0, _marker: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, elem: T) {
+        let node = Box::into_raw(Box::new(Node { elem, link: addr(self.head) }));
+        if self.head.is_null() {
+            self.tail = node;
+        } else {
+            // 先頭ノードの前は null (0) だったので、XOR すれば新しいノードに置き換わる。
+            unsafe { (*self.head).link ^= addr(node) };
+        }
+        self.head = node;
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn push_back(&mut self, elem: T) {
+        let node = Box::into_raw(Box::new(Node { elem, link: addr(self.tail) }));
+        if self.tail.is_null() {
+            self.head = node;
+        } else {
+            unsafe { (*self.tail).link ^= addr(node) };
+        }
+        self.tail = node;
+        self.len += 1;
+        self.validate();
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.head.is_null() {
+            return None;
+        }
+        let elem = unsafe {
+            let old = Box::from_raw(self.head);
+            let next: *mut Node<T> = from_addr(old.link);
+            if next.is_null() {
+                self.tail = ptr::null_mut();
+            } else {
+                (*next).link ^= addr(self.head);
+            }
+            self.head = next;
+            self.len -= 1;
+            old.elem
+        };
+        self.validate();
+        Some(elem)
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.tail.is_null() {
+            return None;
+        }
+        let elem = unsafe {
+            let old = Box::from_raw(self.tail);
+            let prev: *mut Node<T> = from_addr(old.link);
+            if prev.is_null() {
+                self.head = ptr::null_mut();
+            } else {
+                (*prev).link ^= addr(self.tail);
+            }
+            self.tail = prev;
+            self.len -= 1;
+            old.elem
+        };
+        self.validate();
+        Some(elem)
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        unsafe { self.head.as_ref().map(|node| &node.elem) }
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        unsafe { self.tail.as_ref().map(|node| &node.elem) }
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        unsafe { self.head.as_mut().map(|node| &mut node.elem) }
+    }
+
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        unsafe { self.tail.as_mut().map(|node| &mut node.elem) }
+    }
+
+    // XOR は対称なので、どちらの端から辿るかを入れ替えるだけで逆順になる。
+    pub fn reverse(&mut self) {
+        mem::swap(&mut self.head, &mut self.tail);
+        self.validate();
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            front: self.head,
+            front_prev: 0,
+            back: self.tail,
+            back_next: 0,
+            len: self.len,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            front: self.head,
+            front_prev: 0,
+            back: self.tail,
+            back_next: 0,
+            len: self.len,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// 不変条件を確かめ、破れていれば panic する。
+// `head`から`link`を解きながらちょうど`len`個辿ると`tail`に着き、その次が null (0) になる事、
+// 途中で同じノードに戻らない事を確かめる。XOR で繋いでいるので、どこかの`link`が壊れていれば
+// それ以降は正しく辿れず、このどれかが破れる。
+// `validate`フィーチャーを有効にすると、リストを変更する操作のたびに呼ばれる。
+impl<T> List<T> {
+    pub fn debug_assert_invariants(&self) {
+        let mut seen = HashSet::new();
+        let mut prev = 0;
+        let mut cur = self.head;
+        for i in 0..self.len {
+            assert!(!cur.is_null(), "`len` is {}, but only {} nodes are reachable from `head`", self.len, i);
+            assert!(seen.insert(cur), "cycle: {:p} is reachable twice from `head`", cur);
+            let next = unsafe { other(cur, prev) };
+            prev = addr(cur);
+            cur = next;
+        }
+        assert!(cur.is_null(), "more than `len` ({}) nodes are reachable from `head`", self.len);
+        assert!(from_addr::<T>(prev) == self.tail, "`tail` is {:p}, but the last node is {:p}", self.tail, from_addr::<T>(prev));
+    }
+
+    #[inline]
+    fn validate(&self) {
+        if cfg!(feature = "validate") {
+            self.debug_assert_invariants();
+        }
+    }
+}
+
+// ノードの繋がりを Graphviz の DOT 形式で出力する (デバッグ用)。
+// ノードには`link`の値もそのまま書き、`next`と`prev`の矢印は先頭から辿りながら復元したものを書く。
+// `link`が壊れていても止まるように、辿るのは`len`個までにする。
+impl<T: Debug> List<T> {
+    pub fn to_dot(&self) -> String {
+        let mut dot = Dot::new();
+        dot.root("head", self.head as *const Node<T>, "");
+        dot.root("tail", self.tail as *const Node<T>, "");
+        let mut prev = 0;
+        let mut cur = self.head;
+        for _ in 0..self.len {
+            if cur.is_null() {
+                break;
+            }
+            unsafe {
+                let next = other(cur, prev);
+                dot.node(cur as *const Node<T>, &format!("{:?}\nlink={:#x}", (*cur).elem, (*cur).link));
+                dot.edge(cur as *const Node<T>, next as *const Node<T>, "next");
+                dot.edge(cur as *const Node<T>, from_addr::<T>(prev) as *const Node<T>, "prev");
+                prev = addr(cur);
+                cur = next;
+            }
+        }
+        dot.finish()
+    }
+}
+
+impl<T> Default for List<T> {
+    fn default() -> Self {
+        List::new()
+    }
+}
+
+impl<T> IntoIterator for List<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a List<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut List<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T> Drop for List<T> {
+    fn drop(&mut self) {
+        // 要素の drop が panic しても、残りの要素は`DropGuard`が drop し続ける。
+        // (その途中でさらに panic すると abort になる。)
+        struct DropGuard<'a, T: 'a>(&'a mut List<T>);
+        impl<'a, T> Drop for DropGuard<'a, T> {
+            fn drop(&mut self) {
+                while self.0.pop_front().is_some() {}
+            }
+        }
+        let guard = DropGuard(self);
+        while guard.0.pop_front().is_some() {}
+    }
+}
+
+unsafe impl<T: Send> Send for List<T> {}
+unsafe impl<T: Sync> Sync for List<T> {}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.front;
+            self.front = other(node, self.front_prev);
+            self.front_prev = addr(node as *mut Node<T>);
+            Some(&(*node).elem)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.back;
+            self.back = other(node, self.back_next);
+            self.back_next = addr(node as *mut Node<T>);
+            Some(&(*node).elem)
+        }
+    }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.front;
+            self.front = other(node, self.front_prev);
+            self.front_prev = addr(node);
+            Some(&mut (*node).elem)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            let node = self.back;
+            self.back = other(node, self.back_next);
+            self.back_next = addr(node);
+            Some(&mut (*node).elem)
+        }
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.0.pop_front()
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{self, AssertUnwindSafe};
+    use super::List;
+
+    #[test]
+    fn basics() {
+        let mut list = List::new();
+        assert_eq!(list.pop_front(), None);
+        assert_eq!(list.pop_back(), None);
+
+        list.push_back(2);
+        list.push_back(3);
+        list.push_front(1);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.front(), Some(&1));
+        assert_eq!(list.back(), Some(&3));
+
+        assert_eq!(list.pop_back(), Some(3));
+        assert_eq!(list.pop_front(), Some(1));
+        assert_eq!(list.pop_front(), Some(2));
+        assert_eq!(list.pop_front(), None);
+        assert!(list.is_empty());
+
+        list.push_front(4);
+        *list.front_mut().unwrap() += 1;
+        *list.back_mut().unwrap() += 1;
+        assert_eq!(list.pop_back(), Some(6));
+        assert_eq!(list.back(), None);
+    }
+
+    #[test]
+    fn reverse() {
+        let mut list = List::new();
+        for i in 0..5 {
+            list.push_back(i);
+        }
+        list.reverse();
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), vec![4, 3, 2, 1, 0]);
+
+        // 反転したあとも両端の操作はそのまま使える。
+        list.push_front(5);
+        list.push_back(-1);
+        assert_eq!(list.pop_front(), Some(5));
+        assert_eq!(list.pop_back(), Some(-1));
+        list.reverse();
+        assert_eq!(list.into_iter().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn iter() {
+        let mut list = List::new();
+        for i in 0..6 {
+            list.push_back(i);
+        }
+        assert_eq!(list.iter().rev().cloned().collect::<Vec<_>>(), vec![5, 4, 3, 2, 1, 0]);
+
+        let mut iter = list.iter();
+        assert_eq!(iter.next(), Some(&0));
+        assert_eq!(iter.next_back(), Some(&5));
+        assert_eq!(iter.next(), Some(&1));
+        assert_eq!(iter.next_back(), Some(&4));
+        assert_eq!(iter.next(), Some(&2));
+        assert_eq!(iter.next(), Some(&3));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut list = List::new();
+        for i in 0..4 {
+            list.push_back(i);
+        }
+        for v in &mut list {
+            *v *= 10;
+        }
+        let mut sum = 0;
+        for v in &list {
+            sum += *v;
+        }
+        assert_eq!(sum, 60);
+        let mut iter = list.iter_mut();
+        assert_eq!(iter.next_back(), Some(&mut 30));
+        assert_eq!(iter.next(), Some(&mut 0));
+
+        let mut iter = list.into_iter();
+        assert_eq!(iter.next_back(), Some(30));
+        assert_eq!(iter.collect::<Vec<_>>(), vec![0, 10, 20]);
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut list = List::new();
+        list.push_back(1);
+        list.push_back(2);
+        list.push_back(3);
+        let out = list.to_dot();
+        // `link`から復元した前後の矢印が揃っている。
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 2);
+        assert_eq!(out.matches(" [label=\"prev\"]").count(), 2);
+        let head = format!("\"{:p}\"", list.head);
+        let tail = format!("\"{:p}\"", list.tail);
+        assert!(out.contains(&format!("\"head\" -> {};", head)));
+        assert!(out.contains(&format!("\"tail\" -> {};", tail)));
+        // 先頭の`link`は次のノードのアドレスそのもの。
+        let second = unsafe { (*list.head).link };
+        assert!(out.contains(&format!("\\n1\\nlink={:#x}\"", second)));
+    }
+
+    #[test]
+    fn invariants() {
+        let mut list = List::new();
+        list.debug_assert_invariants();
+        for i in 0..3 {
+            list.push_back(i);
+        }
+        list.reverse();
+        list.debug_assert_invariants();
+
+        // `len`が実際のノード数と合わない状態。
+        list.len = 2;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        list.len = 3;
+
+        // 先頭の`link`が壊れた状態。
+        let link = unsafe { (*list.head).link };
+        unsafe { (*list.head).link = 0 };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| list.debug_assert_invariants()));
+        assert!(result.is_err());
+        unsafe { (*list.head).link = link };
+        list.debug_assert_invariants();
+    }
+}
@@ -0,0 +1,184 @@
+// `persistent_stack::List`の上のジッパー。
+// リストのある位置 (フォーカス) を、そこより左の要素を逆順に並べたリストと、
+// フォーカスから右の残りのリストの組で表す。
+// どちらのリストも先頭だけを触れば移動や編集ができるので、各操作は O(1) になる。
+
+// `persistent_stack::List`と同じく、操作は`&self`を取って新しいジッパーを返す。
+// 触らなかった側のリストや、触った側の先頭以外のノードは元のジッパーと共有される。
+// 要素を左右のリストの間で移すときはノードを作り直すので、`T: Clone`が必要になる。
+
+use std::fmt::Debug;
+use persistent_stack::{self, List};
+
+pub struct Zipper<T> {
+    // 先頭がフォーカスのすぐ左の要素。
+    left: List<T>,
+    // 先頭がフォーカス。空ならフォーカスは末尾の次 (要素なし) にある。
+    right: List<T>,
+}
+
+impl<T: Clone> Zipper<T> {
+    // フォーカスはリストの先頭。
+    pub fn new(list: List<T>) -> Self {
+        Zipper { left: List::new(), right: list }
+    }
+
+    pub fn focus(&self) -> Option<&T> {
+        self.right.head()
+    }
+
+    pub fn is_start(&self) -> bool {
+        self.left.head().is_none()
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.right.head().is_none()
+    }
+
+    // 先頭にいる場合は`None`。
+    pub fn move_left(&self) -> Option<Zipper<T>> {
+        let elem = self.left.head()?.clone();
+        Some(Zipper { left: self.left.tail(), right: self.right.append(elem) })
+    }
+
+    // 末尾の次にいる場合は`None`。
+    pub fn move_right(&self) -> Option<Zipper<T>> {
+        let elem = self.right.head()?.clone();
+        Some(Zipper { left: self.left.append(elem), right: self.right.tail() })
+    }
+
+    // フォーカスの要素を置き換える。フォーカスがなければ`None`。
+    pub fn replace(&self, elem: T) -> Option<Zipper<T>> {
+        self.right.head()?;
+        Some(Zipper { left: self.left.clone(), right: self.right.tail().append(elem) })
+    }
+
+    // フォーカスの前に挿入し、挿入した要素がフォーカスになる。
+    pub fn insert(&self, elem: T) -> Zipper<T> {
+        Zipper { left: self.left.clone(), right: self.right.append(elem) }
+    }
+
+    // フォーカスの要素を取り除き、次の要素がフォーカスになる。フォーカスがなければ`None`。
+    pub fn delete(&self) -> Option<Zipper<T>> {
+        self.right.head()?;
+        Some(Zipper { left: self.left.clone(), right: self.right.tail() })
+    }
+
+    // 左側の要素を右側に積み直してリストに戻す。
+    // 右側はそのまま共有されるので、かかるのはフォーカスより左の要素数ぶんだけ。
+    pub fn into_list(self) -> List<T> {
+        let mut list = self.right;
+        for elem in self.left.iter() {
+            list = list.append(elem.clone());
+        }
+        list
+    }
+}
+
+// 左右のリストを1つの図にまとめる (デバッグ用)。
+// 編集前のジッパーと共有しているノードは strong count が 2 以上になる。
+impl<T: Debug> Zipper<T> {
+    pub fn to_dot(&self) -> String {
+        persistent_stack::write_dot(&[("left".to_string(), &self.left), ("right".to_string(), &self.right)])
+    }
+}
+
+impl<T> Clone for Zipper<T> {
+    fn clone(&self) -> Self {
+        Zipper { left: self.left.clone(), right: self.right.clone() }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use persistent_stack::List;
+    use super::Zipper;
+
+    fn to_vec(list: &List<i32>) -> Vec<i32> {
+        list.iter().cloned().collect()
+    }
+
+    fn list(elems: &[i32]) -> List<i32> {
+        elems.iter().rev().fold(List::new(), |list, &e| list.append(e))
+    }
+
+    #[test]
+    fn navigation() {
+        let z = Zipper::new(list(&[1, 2, 3]));
+        assert!(z.is_start());
+        assert_eq!(z.focus(), Some(&1));
+        assert!(z.move_left().is_none());
+
+        let z = z.move_right().unwrap().move_right().unwrap();
+        assert_eq!(z.focus(), Some(&3));
+        let end = z.move_right().unwrap();
+        assert!(end.is_end());
+        assert_eq!(end.focus(), None);
+        assert!(end.move_right().is_none());
+
+        let z = end.move_left().unwrap().move_left().unwrap();
+        assert_eq!(z.focus(), Some(&2));
+        assert_eq!(to_vec(&z.into_list()), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn editing() {
+        let start = Zipper::new(list(&[1, 2, 3]));
+        let z = start.move_right().unwrap();
+
+        let replaced = z.replace(20).unwrap();
+        assert_eq!(replaced.focus(), Some(&20));
+        let inserted = replaced.insert(15);
+        assert_eq!(inserted.focus(), Some(&15));
+        let deleted = inserted.move_right().unwrap().delete().unwrap();
+        assert_eq!(deleted.focus(), Some(&3));
+
+        // 末尾の次でも挿入はできるが、置き換えと削除はできない。
+        let end = deleted.move_right().unwrap();
+        assert!(end.replace(0).is_none());
+        assert!(end.delete().is_none());
+        let appended = end.insert(4);
+
+        assert_eq!(to_vec(&appended.into_list()), vec![1, 15, 3, 4]);
+        assert_eq!(to_vec(&deleted.into_list()), vec![1, 15, 3]);
+        assert_eq!(to_vec(&replaced.into_list()), vec![1, 20, 3]);
+        // 以前の状態は変わらない。
+        assert_eq!(to_vec(&z.into_list()), vec![1, 2, 3]);
+        assert_eq!(to_vec(&start.into_list()), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn shares_structure() {
+        let z = Zipper::new(list(&[1, 2, 3, 4])).move_right().unwrap();
+
+        // 移動しても、移した要素以外のノードは共有されている。
+        let moved = z.move_right().unwrap();
+        assert!(moved.left.tail().ptr_eq(&z.left));
+        assert!(moved.right.ptr_eq(&z.right.tail()));
+
+        // 編集では反対側のリストがそのまま共有される。
+        let replaced = z.replace(20).unwrap();
+        assert!(replaced.left.ptr_eq(&z.left));
+        assert!(replaced.right.tail().ptr_eq(&z.right.tail()));
+        let inserted = z.insert(0);
+        assert!(inserted.right.tail().ptr_eq(&z.right));
+        let deleted = z.delete().unwrap();
+        assert!(deleted.right.ptr_eq(&z.right.tail()));
+
+        // リストに戻しても、フォーカスから右は元のリストのノードのまま。
+        let rebuilt = replaced.clone().into_list();
+        assert!(rebuilt.tail().ptr_eq(&replaced.right));
+        assert!(rebuilt.tail().tail().ptr_eq(&z.right.tail()));
+    }
+
+    #[test]
+    fn to_dot() {
+        let z = Zipper::new(list(&[1, 2, 3])).move_right().unwrap();
+        let out = z.to_dot();
+        // 左右のリストがそれぞれ根になる。
+        assert!(out.contains("\"left\" -> "));
+        assert!(out.contains("\"right\" -> "));
+        assert_eq!(out.matches("strong=").count(), 3);
+        assert_eq!(out.matches(" [label=\"next\"]").count(), 1);
+    }
+}